@@ -1,4 +1,5 @@
-local url, clientName = ...
+local url, clientName, authToken = ...
+local protocolVersion = 3
 local terms = { term.native(), peripheral.find 'monitor' }
 
 for _, term in ipairs(terms) do
@@ -87,6 +88,21 @@ local function dec(h)
   return function(x) s(x) end
 end
 
+local crcTable = {}
+for i = 0, 255 do
+  local c = i
+  for _ = 1, 8 do
+    if bit.band(c, 1) ~= 0 then c = bit.bxor(bit.brshift(c, 1), 0xEDB88320) else c = bit.brshift(c, 1) end
+  end
+  crcTable[i] = c
+end
+
+local function frame(x)
+  local c = 0xFFFFFFFF
+  for i = 1, #x do c = bit.bxor(crcTable[bit.band(bit.bxor(c, string.byte(x, i)), 0xFF)], bit.brshift(c, 8)) end
+  return '$' .. #x .. ':' .. bit.bxor(c, 0xFFFFFFFF) .. ':' .. x
+end
+
 local function callRS(p, f, ...)
   if p then return peripheral.call(p, f, ...)
   else return rs[f](...) end
@@ -106,6 +122,10 @@ local function exec(p, r)
       callRS(p.p, 'setBundledOutput', p.s, v)
     else callRS(p.p, 'setAnalogOutput', p.s, p.v) end
   elseif p.o == 't' then r.r = { turtle[p.f](table.unpack(p.v)) }
+  elseif p.o == 'e' then r.r = { assert(load('return ' .. p.e, '=eval'))() }
+  elseif p.o == 'p' then
+  elseif p.o == 'v' then
+    if p.v ~= protocolVersion then log { t = 'Server protocol ' .. p.v .. ', client ' .. protocolVersion, c = 1 } end
   else error('invalid op: ' .. tostring(p.o)) end
   return 0
 end
@@ -131,7 +151,8 @@ while true do
   end
   if socket then
     log { t = 'Connected', c = 13 }
-    local out, tasks = enc(clientName), {}
+    local out, tasks = enc(protocolVersion) .. enc(clientName), {}
+    if authToken then out = enc(authToken) .. out end
     local handler = dec(function(p)
       for _, p in ipairs(p) do
         local task = coroutine.create(exec)
@@ -140,8 +161,8 @@ while true do
         if not e then
           r.r = nil
           r.e = d
-          out = out .. enc(r)
-        elseif type(d) == 'number' then out = out .. enc(r)
+          out = out .. frame(enc(r))
+        elseif type(d) == 'number' then out = out .. frame(enc(r))
         else tasks[#tasks + 1] = { task = task, filter = d, r = r } end
       end
     end)
@@ -170,8 +191,8 @@ while true do
           if not e then
             v.r.r = nil
             v.r.e = d
-            out = out .. enc(v.r)
-          elseif type(d) == 'number' then out = out .. enc(v.r)
+            out = out .. frame(enc(v.r))
+          elseif type(d) == 'number' then out = out .. frame(enc(v.r))
           else newTasks[#newTasks + 1] = { task = v.task, filter = d, r = v.r } end
         else newTasks[#newTasks + 1] = v end
       end
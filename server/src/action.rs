@@ -1,12 +1,14 @@
 use super::lua_value::{vec_to_table, Table, Value};
-use flexstr::LocalStr;
+use flexstr::{local_fmt, LocalStr};
 use std::{
     cell::RefCell,
     future::Future,
     pin::Pin,
     rc::Rc,
     task::{Context, Poll, Waker},
+    time::Duration,
 };
+use tokio::time::sleep;
 
 pub trait Action: 'static {
     type Output;
@@ -18,11 +20,13 @@ struct ActionState<T: Action> {
     result: Option<Result<T::Output, LocalStr>>,
     waker: Option<Waker>,
     action: Option<T>,
+    remote_failure: bool,
 }
 
 pub trait ActionRequest {
     fn build_request(&mut self, table: &mut Table);
     fn on_fail(&mut self, reason: LocalStr);
+    fn on_error(&mut self, reason: LocalStr);
     fn on_response(&mut self, result: Value) -> Result<(), LocalStr>;
 }
 
@@ -36,6 +40,11 @@ impl<T: Action> ActionRequest for ActionState<T> {
         }
     }
 
+    fn on_error(&mut self, reason: LocalStr) {
+        self.remote_failure = true;
+        self.on_fail(reason)
+    }
+
     fn on_response(&mut self, result: Value) -> Result<(), LocalStr> {
         let result = T::parse_response(result);
         let ret = if let Err(ref e) = result { Err(e.clone()) } else { Ok(()) };
@@ -53,6 +62,10 @@ impl<T: Action> Clone for ActionFuture<T> {
     fn clone(&self) -> Self { ActionFuture(self.0.clone()) }
 }
 
+impl<T: Action> ActionFuture<T> {
+    pub fn is_remote_failure(&self) -> bool { self.0.borrow().remote_failure }
+}
+
 impl<T: Action> Future for ActionFuture<T> {
     type Output = Result<T::Output, LocalStr>;
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
@@ -69,7 +82,12 @@ impl<T: Action> Future for ActionFuture<T> {
 
 impl<T: Action> From<T> for ActionFuture<T> {
     fn from(action: T) -> Self {
-        ActionFuture(Rc::new(RefCell::new(ActionState { result: None, waker: None, action: Some(action) })))
+        ActionFuture(Rc::new(RefCell::new(ActionState {
+            result: None,
+            waker: None,
+            action: Some(action),
+            remote_failure: false,
+        })))
     }
 }
 
@@ -77,6 +95,41 @@ impl<T: Action> From<ActionFuture<T>> for Rc<RefCell<dyn ActionRequest>> {
     fn from(future: ActionFuture<T>) -> Self { future.0 }
 }
 
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    pub n_retries: u32,
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self { RetryPolicy { n_retries: 2, base_delay: Duration::from_millis(100) } }
+}
+
+impl RetryPolicy {
+    // Only errors raised by the peripheral call itself are retried, as those are thrown before anything moves.
+    // A dropped connection may have left the action executed, so it fails straight away.
+    pub async fn run<T: Action>(
+        self,
+        mut action: ActionFuture<T>,
+        mut reissue: impl FnMut() -> Result<ActionFuture<T>, LocalStr>,
+    ) -> Result<T::Output, LocalStr> {
+        let mut delay = self.base_delay;
+        let mut n_tries = 0;
+        loop {
+            match action.clone().await {
+                Err(_) if n_tries < self.n_retries && action.is_remote_failure() => {
+                    sleep(delay).await;
+                    delay *= 2;
+                    n_tries += 1;
+                    action = reissue()?
+                }
+                Err(e) if n_tries > 0 => break Err(local_fmt!("{} (gave up after {} retries)", e, n_tries)),
+                result => break result,
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Log {
     pub text: LocalStr,
@@ -95,6 +148,46 @@ impl Action for Log {
     fn parse_response(_: Value) -> Result<(), LocalStr> { Ok(()) }
 }
 
+pub const PROTOCOL_VERSION: i32 = 3;
+// Clients from this version on wrap each packet they send in a checksummed frame.
+pub const FRAMED_VERSION: i32 = 3;
+
+pub struct ProtocolVersion;
+
+impl Action for ProtocolVersion {
+    type Output = ();
+
+    fn build_request(self, table: &mut Table) {
+        table.insert("o".into(), "v".into());
+        table.insert("v".into(), PROTOCOL_VERSION.into());
+    }
+
+    fn parse_response(_: Value) -> Result<(), LocalStr> { Ok(()) }
+}
+
+pub struct Ping;
+
+impl Action for Ping {
+    type Output = ();
+    fn build_request(self, table: &mut Table) { table.insert("o".into(), "p".into()); }
+    fn parse_response(_: Value) -> Result<(), LocalStr> { Ok(()) }
+}
+
+pub struct Eval {
+    pub expr: LocalStr,
+}
+
+impl Action for Eval {
+    type Output = Value;
+
+    fn build_request(self, table: &mut Table) {
+        table.insert("o".into(), "e".into());
+        table.insert("e".into(), self.expr.into());
+    }
+
+    fn parse_response(response: Value) -> Result<Value, LocalStr> { Ok(response) }
+}
+
 pub struct Call {
     pub addr: LocalStr,
     pub args: Vec<Value>,
@@ -0,0 +1,171 @@
+use crate::{factory::Factory, item::Filter, server::create_listener, util::spawn};
+use abort_on_drop::ChildTask;
+use serde_json::{json, Value};
+use std::{
+    cell::RefCell,
+    io,
+    rc::{Rc, Weak},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+// Bound once and passed from factory to factory on reload, as the old one still holds the port while the new
+// one is built.
+pub struct ApiListener {
+    pub port: u16,
+    listener: TcpListener,
+}
+
+impl ApiListener {
+    pub fn bind(port: u16) -> io::Result<Rc<Self>> { Ok(Rc::new(Self { port, listener: create_listener(port)? })) }
+}
+
+pub fn run(listener: Rc<ApiListener>, factory: Weak<RefCell<Factory>>) -> ChildTask<()> {
+    spawn(async move {
+        let listener = &listener.listener;
+        loop {
+            let Ok((stream, _)) = listener.accept().await else { continue };
+            if factory.upgrade().is_none() {
+                break;
+            }
+            let _ = timeout(Duration::from_secs(5), serve(&factory, stream)).await;
+        }
+    })
+}
+
+async fn serve(factory: &Weak<RefCell<Factory>>, mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|x| x == b"\r\n\r\n") && request.len() < 8192 {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n])
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.split_whitespace();
+    let (method, path) = (words.next().unwrap_or_default(), words.next().unwrap_or_default());
+    let Some(factory) = factory.upgrade() else { return Ok(()) };
+    let response = if !authorized(&factory.borrow(), &request) {
+        "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n".to_owned()
+    } else if let Some(body) = route(&mut factory.borrow_mut(), method, path) {
+        let body = body.to_string();
+        format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}", body.len())
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_owned()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn authorized(factory: &Factory, request: &str) -> bool {
+    let server = factory.config.server.borrow();
+    let Some(token) = &server.auth_token else { return true };
+    request.lines().any(|line| {
+        let Some((key, value)) = line.split_once(':') else { return false };
+        key.eq_ignore_ascii_case("authorization") && value.trim().strip_prefix("Bearer ") == Some(&**token)
+    })
+}
+
+fn route(factory: &mut Factory, method: &str, path: &str) -> Option<Value> {
+    match (method, path) {
+        ("GET", "/recipes") => Some(recipes(factory)),
+        ("GET", "/items") => Some(items(factory)),
+        ("GET", "/storages") => Some(storages(factory)),
+        ("GET", _) if path.starts_with("/items/") => item(factory, &percent_decode(&path["/items/".len()..])?),
+        ("POST", "/reset-stats") => {
+            factory.reset_stats();
+            Some(Value::Null)
+        }
+        _ => None,
+    }
+}
+
+fn recipes(factory: &Factory) -> Value {
+    let mut result = Vec::new();
+    for entry in factory.get_recipe_book() {
+        let inputs: Vec<_> =
+            (entry.inputs.iter()).map(|(item, size)| json!({ "item": &*item.describe(), "size": size })).collect();
+        for output in &entry.outputs {
+            result.push(json!({ "output": &*output.describe(), "process": &*entry.process, "inputs": inputs }))
+        }
+    }
+    result.into()
+}
+
+fn items(factory: &Factory) -> Value {
+    let items = factory.get_item_snapshot(true);
+    items.into_iter().map(|(label, n)| json!({ "label": &*label, "count": n })).collect()
+}
+
+fn storages(factory: &Factory) -> Value {
+    let storages: Vec<_> = (factory.get_storage_occupancy().into_iter())
+        .map(|(name, used, total)| json!({ "name": &*name, "used_slots": used, "total_slots": total }))
+        .collect();
+    let fluids: Vec<_> = (factory.get_fluid_snapshot().into_iter())
+        .map(|(fluid, used, capacity)| json!({ "fluid": &*fluid, "used": used, "capacity": capacity }))
+        .collect();
+    json!({ "storages": storages, "fluids": fluids })
+}
+
+fn item(factory: &Factory, label: &str) -> Option<Value> {
+    let (item, info) = factory.search_item(&Filter::Label(label.into()))?;
+    Some(json!({ "label": label, "count": info.borrow().n_stored, "item": &**item }))
+}
+
+fn percent_decode(x: &str) -> Option<String> {
+    let mut result = Vec::new();
+    let mut bytes = x.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            result.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?)
+        } else {
+            result.push(byte)
+        }
+    }
+    String::from_utf8(result).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::factory::tests::with_test_factory;
+    use crate::recipe::RecipeBookEntry;
+
+    #[test]
+    fn recipes_lists_inputs_and_output() {
+        with_test_factory(|factory, _| async move {
+            factory.borrow_mut().add_to_recipe_book(RecipeBookEntry {
+                process: "workbench".into(),
+                outputs: vec![Filter::Label("Iron Block".into())],
+                inputs: vec![(Filter::Label("Iron Ingot".into()), 9)],
+            });
+            let expected = json!([{
+                "output": "Iron Block",
+                "process": "workbench",
+                "inputs": [{ "item": "Iron Ingot", "size": 9 }],
+            }]);
+            assert_eq!(route(&mut factory.borrow_mut(), "GET", "/recipes"), Some(expected));
+        })
+    }
+
+    #[test]
+    fn reset_stats_zeroes_counters() {
+        with_test_factory(|factory, _| async move {
+            let mut factory = factory.borrow_mut();
+            factory.stats.n_cycles = 10;
+            factory.stats.n_items_moved.set(640);
+            factory.stats.recent_cycle_times.push_back(Duration::from_secs(2));
+            assert_eq!(route(&mut factory, "POST", "/reset-stats"), Some(Value::Null));
+            let status = factory.describe_status();
+            assert!(status.contains(" 0 cycles, avg cycle 0.000s over last 0,"), "{status}");
+            assert!(status.ends_with(" 0 items moved"), "{status}");
+        })
+    }
+}
@@ -0,0 +1,263 @@
+use crate::{
+    action::{ActionFuture, Eval, Log},
+    factory::Factory,
+    item::Filter,
+    recipe::RecipeBookEntry,
+    util::spawn,
+    Tui,
+};
+use abort_on_drop::ChildTask;
+use flexstr::{local_fmt, local_str};
+use ratatui::{
+    style::Color,
+    text::{Line, Span},
+};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    iter::once,
+    rc::{Rc, Weak},
+};
+
+const QUEUE_SIZE: usize = 8;
+
+pub fn run(tui: Rc<Tui>, factory: Weak<RefCell<Factory>>) -> ChildTask<()> {
+    spawn(async move {
+        let mut queue = VecDeque::new();
+        loop {
+            tui.on_input.notified().await;
+            let Some(factory) = factory.upgrade() else { break };
+            let mut factory = factory.borrow_mut();
+            tui.input_queue.borrow_mut().retain(|line| !run_command(&mut factory, &mut queue, line))
+        }
+    })
+}
+
+fn run_command(factory: &mut Factory, queue: &mut VecDeque<ChildTask<()>>, line: &str) -> bool {
+    let args: Vec<_> = line.split_whitespace().collect();
+    match args[..] {
+        ["items"] => show_items(factory, false),
+        ["items", "abc"] => show_items(factory, true),
+        ["fluids"] => show_fluids(factory),
+        // The manual UI redraws the main list each cycle unless another list is pinned over it.
+        ["manual"] => factory.config.tui.unpin_main_list(),
+        ["recipes", ref filter @ ..] => show_recipes(factory, &filter.join(" ")),
+        ["explain", ref label @ ..] if !label.is_empty() => explain(factory, &label.join(" ")),
+        ["reset-stats"] => reset_stats(factory),
+        ["reload"] => factory.reload(),
+        ["status"] => factory.log(Log { text: factory.describe_status(), color: 0 }),
+        ["pause"] => factory.set_paused(true),
+        ["resume"] => factory.set_paused(false),
+        ["enable", name] => set_process_enabled(factory, name, true),
+        ["disable", name] => set_process_enabled(factory, name, false),
+        ["estop"] => factory.emergency_stop(),
+        ["rearm"] => factory.rearm(),
+        ["dryrun", "on"] => set_dry_run(factory, true),
+        ["dryrun", "off"] => set_dry_run(factory, false),
+        ["savelog", path] => save_log(factory, path),
+        ["snapshot", path] => factory.request_snapshot(path.to_owned()),
+        ["turtle", name, ref expr @ ..] if !expr.is_empty() => eval_on_turtle(factory, queue, name, &expr.join(" ")),
+        ["craft", ref label @ .., n] if !label.is_empty() => {
+            let Ok(n) = n.parse() else { return false };
+            craft(factory, &label.join(" "), n)
+        }
+        _ => return false,
+    }
+    true
+}
+
+fn show_items(factory: &Factory, alphabetical: bool) {
+    let fuel = (factory.get_turtle_fuel().iter())
+        .map(|(name, level)| Line::from(Span::styled(format!("{name}: fuel {level}"), Color::Yellow)));
+    let occupancy = factory.get_storage_occupancy();
+    let (used, total) = occupancy.iter().fold((0, 0), |(used, total), x| (used + x.1, total + x.2));
+    let percent = if total > 0 { used * 100 / total } else { 0 };
+    let footer = format!("{used}/{total} slots used across {} storages ({percent}%)", occupancy.len());
+    factory.config.tui.show_main_list(
+        fuel.chain(factory.get_item_snapshot(alphabetical).into_iter().map(|(label, n)| {
+            Line::from(vec![Span::raw(format!("{n} * ")), Span::styled(label.to_std_string(), Color::LightGreen)])
+        }))
+        .chain(once(Line::from(Span::styled(footer, Color::DarkGray))))
+        .collect(),
+    )
+}
+
+fn show_fluids(factory: &Factory) {
+    factory.config.tui.show_main_list(
+        (factory.get_fluid_snapshot().into_iter())
+            .map(|(fluid, n, capacity)| {
+                Line::from(vec![
+                    Span::raw(format!("{n}/{capacity} mB ")),
+                    Span::styled(fluid.to_std_string(), Color::LightBlue),
+                ])
+            })
+            .collect(),
+    )
+}
+
+fn show_recipes(factory: &Factory, filter: &str) {
+    let filter = filter.to_lowercase();
+    let mut lines = Vec::new();
+    for entry in factory.get_recipe_book() {
+        if !entry.outputs.iter().any(|output| output.get_text().to_lowercase().contains(&filter)) {
+            continue;
+        }
+        let outputs: Vec<_> = entry.outputs.iter().map(|x| x.describe()).collect();
+        let inputs: Vec<_> =
+            entry.inputs.iter().map(|(input, size)| format!("{}*{}", input.describe(), size)).collect();
+        lines.push(Line::from(vec![
+            Span::styled(outputs.join(", "), Color::LightGreen),
+            Span::raw(format!(" <- {} ", inputs.join(", "))),
+            Span::styled(format!("({})", entry.process), Color::DarkGray),
+        ]))
+    }
+    factory.config.tui.show_main_list(lines)
+}
+
+fn set_process_enabled(factory: &mut Factory, name: &str, enabled: bool) {
+    let log = if !factory.set_process_enabled(name, enabled) {
+        Log { text: local_fmt!("no process named {}", name), color: 14 }
+    } else if enabled {
+        Log { text: local_fmt!("{}: enabled", name), color: 13 }
+    } else {
+        Log { text: local_fmt!("{}: disabled", name), color: 1 }
+    };
+    factory.log(log)
+}
+
+fn n_available(factory: &Factory, filter: &Filter) -> i32 {
+    factory.search_item(filter).map_or(0, |(_, info)| info.borrow().get_availability(false, 0))
+}
+
+// Recipe outputs whose label equals the query, ignoring case, or failing that contain it.
+fn match_outputs<'a>(factory: &'a Factory, label: &str) -> Vec<(&'a RecipeBookEntry, &'a Filter)> {
+    let query = label.to_lowercase();
+    let outputs =
+        || (factory.get_recipe_book().iter()).flat_map(|entry| entry.outputs.iter().map(move |output| (entry, output)));
+    let exact: Vec<_> = outputs().filter(|(_, output)| output.get_text().to_lowercase() == query).collect();
+    if !exact.is_empty() {
+        return exact;
+    }
+    outputs().filter(|(_, output)| output.get_text().to_lowercase().contains(&query)).collect()
+}
+
+fn describe_candidates(candidates: &[(&RecipeBookEntry, &Filter)]) -> String {
+    let mut names: Vec<_> =
+        candidates.iter().map(|(entry, output)| local_fmt!("{} ({})", output.describe(), entry.process)).collect();
+    names.sort();
+    names.dedup();
+    names.join(", ")
+}
+
+fn explain(factory: &Factory, label: &str) {
+    let candidates = match_outputs(factory, label);
+    if candidates.iter().any(|(_, output)| *output != candidates[0].1) {
+        let text = format!("{label} is ambiguous, candidates: {}", describe_candidates(&candidates));
+        return factory.config.tui.show_main_list(vec![Line::from(Span::styled(text, Color::Yellow))]);
+    }
+    let mut lines = Vec::new();
+    for (entry, output) in candidates {
+        let mut line = vec![
+            Span::styled(output.describe().to_std_string(), Color::LightGreen),
+            Span::raw(format!(" ({})", entry.process)),
+        ];
+        if let Some(held_by) = factory.process_held_by(&entry.process) {
+            line.push(Span::styled(format!(" ({held_by})"), Color::Yellow))
+        }
+        lines.push(Line::from(line));
+        for (input, size) in &entry.inputs {
+            let n = n_available(factory, input);
+            lines.push(if n < *size {
+                Line::from(Span::styled(
+                    format!("  {}: {}/{}, missing {}", input.describe(), n, size, size - n),
+                    Color::Red,
+                ))
+            } else {
+                Line::from(format!("  {}: {}/{}", input.describe(), n, size))
+            })
+        }
+    }
+    if lines.is_empty() {
+        lines.push(Line::from(Span::styled(format!("no recipe produces {label}"), Color::Red)))
+    } else if factory.is_estopped() {
+        lines.push(Line::from(Span::styled("factory is emergency stopped", Color::Red)))
+    } else if factory.is_paused() {
+        lines.push(Line::from(Span::styled("factory is paused", Color::Yellow)))
+    }
+    factory.config.tui.show_main_list(lines)
+}
+
+fn reset_stats(factory: &mut Factory) {
+    factory.reset_stats();
+    factory.log(Log { text: local_str!("stats reset"), color: 13 })
+}
+
+fn save_log(factory: &Factory, path: &str) {
+    let tui = &factory.config.tui;
+    match tui.save_log(path) {
+        Ok(n) => tui.log(format!("saved {n} log lines to {path}"), 13),
+        Err(e) => tui.log(format!("savelog {path}: {e}"), 14),
+    }
+}
+
+fn set_dry_run(factory: &Factory, dry_run: bool) {
+    factory.config.server.borrow_mut().dry_run = dry_run;
+    let text = if dry_run { local_str!("dry run on, transfers are logged only") } else { local_str!("dry run off") };
+    factory.log(Log { text, color: 1 })
+}
+
+fn eval_on_turtle(factory: &Factory, queue: &mut VecDeque<ChildTask<()>>, name: &str, expr: &str) {
+    let tui = factory.config.tui.clone();
+    if !factory.config.turtle_clients.iter().any(|x| &**x == name) {
+        return tui.log(format!("turtle {name}: no turtle process runs on this client"), 14);
+    }
+    let server = factory.config.server.borrow();
+    if !server.is_connected(name) {
+        return tui.log(format!("turtle {name}: not connected"), 14);
+    }
+    let action = ActionFuture::from(Eval { expr: expr.into() });
+    server.enqueue_request_group(name, vec![action.clone().into()]);
+    if queue.len() == QUEUE_SIZE {
+        queue.pop_front();
+        tui.log(format!("turtle: over {QUEUE_SIZE} evals pending, dropped the oldest one's result"), 1)
+    }
+    let name = name.to_owned();
+    queue.push_back(spawn(async move {
+        match action.await {
+            Ok(result) => tui.log(format!("{name}: {result:?}"), 0),
+            Err(e) => tui.log(format!("{name}: {e}"), 14),
+        }
+    }))
+}
+
+fn craft(factory: &mut Factory, label: &str, n: i32) {
+    if n < 1 {
+        return factory.log(Log { text: local_fmt!("craft {}: count must be at least 1", label), color: 14 });
+    }
+    let candidates = match_outputs(factory, label);
+    let Some(&(_, item)) = candidates.first() else {
+        return factory.log(Log { text: local_fmt!("craft {}: no matching recipe", label), color: 14 });
+    };
+    if candidates.iter().any(|(_, output)| *output != item) {
+        let text = local_fmt!("craft {}: ambiguous, candidates: {}", label, describe_candidates(&candidates));
+        return factory.log(Log { text, color: 1 });
+    }
+    let mut missing = Vec::new();
+    let craftable = candidates.iter().any(|(entry, _)| {
+        missing.clear();
+        for (input, size) in &entry.inputs {
+            if n_available(factory, input) < *size {
+                missing.push(input.describe())
+            }
+        }
+        missing.is_empty()
+    });
+    let item: Filter = item.clone();
+    if craftable {
+        factory.log(Log { text: local_fmt!("craft {}*{}: scheduled", item.describe(), n), color: 13 });
+        factory.request_craft(item, n)
+    } else {
+        let text = local_fmt!("craft {}*{}: blocked on {}", item.describe(), n, missing.join(", "));
+        factory.log(Log { text, color: 6 })
+    }
+}
@@ -1,30 +1,126 @@
 use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
 use serde::Deserialize;
+use serde_json::Value;
 use std::{
     cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs,
+    path::{Path, PathBuf},
     rc::Rc,
-    sync::{mpsc::channel, Arc, Mutex},
+    sync::mpsc::channel,
     thread,
     time::Duration,
 };
 
-use crate::factory::{Factory, FactoryConfig};
+use crate::factory::{AlertRule, Factory, FactoryConfig, FactorySlot, FluidAlertRule, ProcessOptions};
+use crate::item::{DetailStack, Filter, Relabel};
 use crate::{access::*, config_util::*, process::*, recipe::*, storage::*};
-use crate::{detail_cache::DetailCache, server::Server, Tui};
+use crate::{action::RetryPolicy, api::ApiListener, detail_cache::DetailCache, log_file::LogFile};
+use crate::{server, server::Server, statsd::StatsD, webhook::Webhook, Tui};
 
+// A config may also list `includes`, paths relative to itself whose contents are merged in at load time.
 #[derive(Deserialize)]
 pub struct DynamicFactoryConfig {
     pub server_port: u16,
     pub min_cycle_time_secs: u64,
+    pub max_cycle_time_secs: Option<u64>,
+    #[serde(default)]
+    pub cycle_jitter_ms: u64,
     pub log_clients: Vec<String>,
     pub bus_accesses: Vec<BusAccessConfig>,
+    #[serde(default)]
+    pub access_groups: HashMap<String, Vec<BusAccessConfig>>,
     pub fluid_bus_accesses: Vec<FluidBusConfig>,
-    pub fluid_bus_capacity: i32,
+    pub fluid_bus_capacity: i64,
     pub storages: Vec<StorageConfig>,
-    pub processes: Vec<ProcessConfig>,
-    pub backups: Vec<BusAccessConfig>,
-    pub fluid_backups: Vec<FluidBusConfig>,
+    pub processes: Vec<ProcessEntryConfig>,
+    pub backups: Vec<BackupEntry<BackupConfig, BusAccessConfig>>,
+    pub fluid_backups: Vec<BackupEntry<FluidBackupConfig, FluidBusConfig>>,
+    pub warn_transfer_size: Option<i32>,
+    pub bus_stuck_cycles: Option<usize>,
+    pub stall_cycles: Option<usize>,
+    pub status_interval_secs: Option<u64>,
+    pub background_budget: Option<f64>,
+    pub statsd_addr: Option<String>,
+    // Warnings and errors are posted here as Discord-style {"content": ...} JSON.
+    pub webhook_url: Option<String>,
+    #[serde(default)]
+    pub relabel: Vec<RelabelConfig>,
+    #[serde(default)]
+    pub trash: Vec<ItemFilter>,
+    pub trash_addr: Option<String>,
+    pub api_port: Option<u16>,
+    #[serde(default)]
+    pub fair_scheduling: bool,
+    pub log_file: Option<String>,
+    pub auth_token: Option<String>,
+    pub client_timeout_secs: Option<u64>,
+    pub action_timeout_ms: Option<u64>,
+    pub reconnect_grace_secs: Option<u64>,
+    #[serde(default)]
+    pub strict_protocol_version: bool,
+    pub max_in_flight: Option<usize>,
+    pub transfer_retries: Option<u32>,
+    pub transfer_retry_delay_ms: Option<u64>,
+    pub craft_queue_file: Option<String>,
+    #[serde(default)]
+    pub alerts: Vec<AlertRuleConfig>,
+    #[serde(default)]
+    pub fluid_alerts: Vec<FluidAlertRuleConfig>,
+}
+
+// Amounts kept in storage that only inputs with allow_backup may consume.
+#[derive(Deserialize)]
+pub struct BackupConfig {
+    pub item: ItemFilter,
+    pub qty: i32,
+}
+
+#[derive(Deserialize)]
+pub struct FluidBackupConfig {
+    pub fluid: String,
+    pub qty: i64,
+}
+
+// Backups used to be parsed as bus accesses, which never reserved anything.
+// Configs in that shape still load, but those entries are skipped with a warning.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum BackupEntry<T, Legacy> {
+    Amount(T),
+    Legacy(Legacy),
+}
+
+impl<T, Legacy> BackupEntry<T, Legacy> {
+    fn amount(&self) -> Option<&T> {
+        match self {
+            BackupEntry::Amount(x) => Some(x),
+            BackupEntry::Legacy(_) => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AlertRuleConfig {
+    pub item: ItemFilter,
+    pub min: i32,
+    #[serde(default)]
+    pub once: bool,
+}
+
+#[derive(Deserialize)]
+pub struct FluidAlertRuleConfig {
+    pub fluid: String,
+    pub min: i64,
+    #[serde(default)]
+    pub once: bool,
+}
+
+// Treats stock under the registry name `from` as `to`, for items renamed by a mod update.
+#[derive(Deserialize)]
+pub struct RelabelConfig {
+    pub from: String,
+    pub to: String,
 }
 
 #[derive(Deserialize)]
@@ -33,23 +129,81 @@ pub struct BusAccessConfig {
     pub addr: String,
 }
 
+// Either the accesses themselves or the name of an entry in `access_groups`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum AccessesConfig {
+    List(Vec<BusAccessConfig>),
+    Group(String),
+}
+
+impl AccessesConfig {
+    fn resolve<'a>(
+        &'a self,
+        groups: &'a HashMap<String, Vec<BusAccessConfig>>,
+    ) -> Result<&'a [BusAccessConfig], String> {
+        match self {
+            AccessesConfig::List(accesses) => Ok(accesses),
+            AccessesConfig::Group(name) => {
+                groups.get(name).map(Vec::as_slice).ok_or_else(|| format!("unknown access group {}", name))
+            }
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct FluidBusConfig {
     pub client: String,
     pub fluid_bus_addrs: Vec<String>,
     pub tank_addr: String,
+    // By bus addr, for buses that hold more or less than fluid_bus_capacity.
+    #[serde(default)]
+    pub capacities: HashMap<String, i64>,
 }
 
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 pub enum StorageConfig {
     Chest {
-        accesses: Vec<BusAccessConfig>,
+        accesses: AccessesConfig,
         override_max_stack_size: Option<i32>,
+        #[serde(default)]
+        stack_overrides: Vec<StackOverride>,
+        #[serde(default)]
+        accept: Vec<ItemFilter>,
+        #[serde(default)]
+        reject: Vec<ItemFilter>,
+        #[serde(default)]
+        min_extract_count: i32,
+        #[serde(default)]
+        priority: i32,
+        weight: Option<f64>,
+        #[serde(default)]
+        read_only: bool,
+        #[serde(default)]
+        extract_only: bool,
+        #[serde(default)]
+        insert_only: bool,
     },
     Drawer {
-        accesses: Vec<BusAccessConfig>,
+        accesses: AccessesConfig,
+        #[serde(default)]
         filters: Vec<ItemFilter>,
+        #[serde(default)]
+        min_extract_count: i32,
+        #[serde(default)]
+        void: bool,
+        #[serde(default)]
+        controller: bool,
+        #[serde(default)]
+        priority: i32,
+        weight: Option<f64>,
+        #[serde(default)]
+        read_only: bool,
+        #[serde(default)]
+        extract_only: bool,
+        #[serde(default)]
+        insert_only: bool,
     },
 }
 
@@ -57,6 +211,7 @@ pub enum StorageConfig {
 #[serde(tag = "type")]
 pub enum ItemFilter {
     Label { value: String },
+    LabelFuzzy { value: String },
     Name { value: String },
     Both { label: String, name: String },
     Custom { desc: String },
@@ -65,12 +220,21 @@ pub enum ItemFilter {
 #[derive(Deserialize)]
 pub struct SlottedInput {
     pub item: ItemFilter,
+    #[serde(default)]
     pub slots: Vec<SlotConfig>,
+    // Number of grid slots a shapeless input takes, one item each; defaults to 1.
+    pub count: Option<usize>,
     pub allow_backup: bool,
     pub extra_backup: i32,
 }
 
 #[derive(Deserialize)]
+pub struct StackOverride {
+    pub item: ItemFilter,
+    pub size: i32,
+}
+
+#[derive(Deserialize, Clone)]
 pub struct SlotConfig {
     pub slot: usize,
     pub size: i32,
@@ -81,36 +245,179 @@ pub struct CraftingRecipe {
     pub outputs: Vec<ItemFilter>,
     pub inputs: Vec<SlottedInput>,
     pub max_sets: i32,
+    #[serde(default)]
+    pub n_wanted: i32,
+    #[serde(default)]
+    pub byproducts: Vec<ByproductConfig>,
+    // Higher priorities are tried first each cycle, ahead of demand.
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub shapeless: bool,
+    // Hard stop once this many of each output are stored, even if a craft request asks for more.
+    pub max_stock: Option<i32>,
+}
+
+impl CraftingRecipe {
+    // Shapeless inputs list no slots and are laid out over the free grid slots in the order listed.
+    // Shaped inputs keep their slots, which must all be inside the grid.
+    fn grid_slots(&self, grid: GridSize) -> Result<Vec<Vec<SlotConfig>>, String> {
+        if !self.shapeless {
+            if self.inputs.iter().any(|x| x.count.is_some()) {
+                return Err("count is only allowed on shapeless recipes".to_owned());
+            }
+            if let Some(slot) = self.inputs.iter().flat_map(|x| &x.slots).find(|x| !grid.contains(x.slot)) {
+                return Err(format!("slot {} is outside the {} crafting grid", slot.slot, grid.describe()));
+            }
+            return Ok(self.inputs.iter().map(|x| x.slots.clone()).collect());
+        }
+        if self.inputs.iter().any(|x| !x.slots.is_empty()) {
+            return Err("shapeless recipes can't bind inputs to slots".to_owned());
+        }
+        let mut free = (0..9).filter(|&slot| grid.contains(slot));
+        let mut result = Vec::new();
+        for input in &self.inputs {
+            let count = input.count.unwrap_or(1);
+            let slots: Vec<_> = free.by_ref().take(count).map(|slot| SlotConfig { slot, size: 1 }).collect();
+            if slots.len() < count {
+                return Err(format!("shapeless recipe needs more slots than the {} grid has", grid.describe()));
+            }
+            result.push(slots)
+        }
+        Ok(result)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ByproductConfig {
+    pub item: ItemFilter,
+    pub max_stored: i32,
+}
+
+#[derive(Deserialize)]
+pub struct ProcessEntryConfig {
+    #[serde(flatten)]
+    pub process: ProcessConfig,
+    #[serde(default)]
+    pub ignore_pause: bool,
+    #[serde(default)]
+    pub background: bool,
+    pub enabled: Option<bool>,
+    pub interval_secs: Option<u64>,
+    #[serde(default)]
+    pub after: Vec<String>,
 }
 
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 pub enum ProcessConfig {
     ManualUI {
-        accesses: Vec<BusAccessConfig>,
+        name: String,
+        accesses: AccessesConfig,
     },
     Workbench {
         name: String,
-        accesses: Vec<BusAccessConfig>,
+        accesses: AccessesConfig,
         recipes: Vec<CraftingRecipe>,
+        #[serde(default)]
+        grid: GridSize,
+        max_crafts_per_cycle: Option<i32>,
     },
     Slotted {
         name: String,
-        accesses: Vec<BusAccessConfig>,
+        accesses: AccessesConfig,
         input_slots: Vec<usize>,
-        extract_filter: Option<String>,
+        extract_filter: Option<ExtractFilterConfig>,
         recipes: Vec<CraftingRecipe>,
         strict_priority: bool,
+        max_crafts_per_cycle: Option<i32>,
     },
     Turtle {
         name: String,
         file_name: String,
         client: String,
+        low_fuel: Option<i32>,
     },
     RedstoneEmitter {
-        accesses: Vec<BusAccessConfig>,
+        name: String,
+        accesses: AccessesConfig,
         output_rules: Vec<RedstoneRule>,
     },
+    Import {
+        name: String,
+        accesses: AccessesConfig,
+        #[serde(default)]
+        filters: Vec<ItemFilter>,
+    },
+    Export {
+        name: String,
+        accesses: AccessesConfig,
+        targets: Vec<ExportTargetConfig>,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct ExportTargetConfig {
+    pub item: ItemFilter,
+    pub slot: usize,
+    pub count: i32,
+}
+
+// Slots are always numbered as on a 3x3 grid, so a 2x2 grid covers slots 0, 1, 3 and 4.
+#[derive(Deserialize, Default, Clone, Copy)]
+pub enum GridSize {
+    #[serde(rename = "2x2")]
+    Grid2x2,
+    #[default]
+    #[serde(rename = "3x3")]
+    Grid3x3,
+}
+
+impl GridSize {
+    fn contains(self, slot: usize) -> bool {
+        match self {
+            GridSize::Grid2x2 => slot < 5 && slot % 3 < 2,
+            GridSize::Grid3x3 => slot < 9,
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            GridSize::Grid2x2 => "2x2",
+            GridSize::Grid3x3 => "3x3",
+        }
+    }
+}
+
+// A bare string is shorthand for a `Name` filter.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ExtractFilterConfig {
+    Name(String),
+    Filter(ItemFilter),
+}
+
+impl ExtractFilterConfig {
+    fn to_filter(&self) -> Filter {
+        match self {
+            ExtractFilterConfig::Name(value) => Filter::Name(s(value)),
+            ExtractFilterConfig::Filter(filter) => filter.to_filter(),
+        }
+    }
+}
+
+impl ProcessConfig {
+    fn name(&self) -> &str {
+        match self {
+            ProcessConfig::ManualUI { name, .. }
+            | ProcessConfig::Workbench { name, .. }
+            | ProcessConfig::Slotted { name, .. }
+            | ProcessConfig::Turtle { name, .. }
+            | ProcessConfig::RedstoneEmitter { name, .. }
+            | ProcessConfig::Import { name, .. }
+            | ProcessConfig::Export { name, .. } => name,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -118,14 +425,25 @@ pub struct RedstoneRule {
     pub name: String,
     pub off_signal: u8,
     pub on_signal: u8,
+    // Without trigger_fill, the rule is on while any of these is in storage.
     pub trigger_items: Vec<ItemFilter>,
+    pub trigger_fill: Option<FillTriggerConfig>,
+}
+
+// Turns the rule on while the inventory at `addr` is at least `percent` full.
+#[derive(Deserialize)]
+pub struct FillTriggerConfig {
+    pub client: String,
+    pub addr: String,
+    pub percent: f64,
 }
 
 impl ItemFilter {
     fn to_filter(&self) -> Filter {
         match self {
             ItemFilter::Label { value } => Filter::Label(s(value)),
-            ItemFilter::Name { value } => Filter::Name(s(value)),
+            ItemFilter::LabelFuzzy { value } => label_fuzzy(value),
+            ItemFilter::Name { value } => name_glob(value),
             ItemFilter::Both { label, name } => Filter::Both {
                 label: s(label),
                 name: s(name),
@@ -138,14 +456,57 @@ impl ItemFilter {
     }
 }
 
-pub fn build_factory_from_json(tui: Rc<Tui>, config_path: &str) -> Rc<RefCell<Factory>> {
-    let config = load_dynamic_config(config_path);
+// The running factory, if any, hands over its server, API listener and detail cache: it still holds their ports and
+// file until it is dropped, which only happens once the new factory is built.
+pub fn build_factory_from_json(
+    tui: Rc<Tui>,
+    config_path: &str,
+    running: Option<&Factory>,
+) -> Result<Rc<RefCell<Factory>>, String> {
+    let config = try_load_dynamic_config(config_path)?;
+    let log_file = (config.log_file.as_ref().map(|path| LogFile::new(path.clone())).transpose())
+        .map_err(|e| format!("Failed to open log file: {}", e))?;
+    for problem in check_config(&config) {
+        tui.log(problem, 1)
+    }
 
-    FactoryConfig {
+    let factory = FactoryConfig {
         tui: tui.clone(),
-        detail_cache: DetailCache::new(&tui, s("detail_cache.txt")),
-        server: Server::new(tui, config.server_port),
+        detail_cache: running
+            .map_or_else(|| DetailCache::new(&tui, s("detail_cache.txt")), |x| x.config.detail_cache.clone()),
+        server: {
+            let kept = running.map(|x| &x.config.server).filter(|x| x.borrow().port() == Some(config.server_port));
+            let server = match kept {
+                Some(server) => server.clone(),
+                None => {
+                    let server = Server::new(tui.clone());
+                    // Moving to another port shouldn't switch a dry run off.
+                    server.borrow_mut().dry_run = running.is_some_and(|x| x.config.server.borrow().dry_run);
+                    (server.borrow_mut().listen(config.server_port))
+                        .map_err(|e| format!("Failed to listen on port {}: {}", config.server_port, e))?;
+                    server
+                }
+            };
+            // A kept server may carry settings the new config no longer has, so every one of them is assigned.
+            let mut server_mut = server.borrow_mut();
+            server_mut.auth_token = config.auth_token.as_ref().map(|x| s(x));
+            server_mut.strict_version = config.strict_protocol_version;
+            server_mut.timeout = config.client_timeout_secs.map_or(server::DEFAULT_TIMEOUT, Duration::from_secs);
+            server_mut.action_timeout =
+                config.action_timeout_ms.map_or(server::DEFAULT_ACTION_TIMEOUT, Duration::from_millis);
+            server_mut.reconnect_grace =
+                config.reconnect_grace_secs.map_or(server::DEFAULT_RECONNECT_GRACE, Duration::from_secs);
+            server_mut.max_in_flight = config.max_in_flight.unwrap_or(server::DEFAULT_MAX_IN_FLIGHT);
+            let retry = RetryPolicy::default();
+            server_mut.retry.n_retries = config.transfer_retries.unwrap_or(retry.n_retries);
+            server_mut.retry.base_delay =
+                config.transfer_retry_delay_ms.map_or(retry.base_delay, Duration::from_millis);
+            drop(server_mut);
+            server
+        },
         min_cycle_time: Duration::from_secs(config.min_cycle_time_secs),
+        max_cycle_time: config.max_cycle_time_secs.map(Duration::from_secs),
+        cycle_jitter: Duration::from_millis(config.cycle_jitter_ms),
         log_clients: config.log_clients.iter().map(|c| s(c)).collect(),
         bus_accesses: config
             .bus_accesses
@@ -164,35 +525,71 @@ pub fn build_factory_from_json(tui: Rc<Tui>, config_path: &str) -> Rc<RefCell<Fa
                 tank_addr: s(&f.tank_addr),
             })
             .collect(),
-        fluid_bus_capacity: config.fluid_bus_capacity,
-        backups: config
-            .backups
-            .iter()
-            .map(|a| BasicAccess {
-                client: s(&a.client),
-                addr: s(&a.addr),
-            })
+        fluid_bus_capacities: fluid_bus_capacities(&config),
+        backups: config.backups.iter().filter_map(BackupEntry::amount).map(|b| (b.item.to_filter(), b.qty)).collect(),
+        fluid_backups: (config.fluid_backups.iter().filter_map(BackupEntry::amount))
+            .map(|b| (s(&b.fluid), b.qty))
             .collect(),
-        fluid_backups: config
-            .fluid_backups
+        warn_transfer_size: config.warn_transfer_size,
+        bus_stuck_cycles: config.bus_stuck_cycles,
+        stall_cycles: config.stall_cycles,
+        status_interval: config.status_interval_secs.map(Duration::from_secs),
+        background_budget: config.background_budget.unwrap_or(1.),
+        statsd: (config.statsd_addr.as_ref().map(|addr| StatsD::new(addr)).transpose())
+            .map_err(|e| format!("Failed to set up StatsD: {}", e))?,
+        // Alerts still reach the log without the webhook, so a bad URL isn't worth refusing the config over.
+        webhook: config.webhook_url.as_ref().and_then(|url| {
+            Webhook::new(url, tui.clone()).map_err(|e| tui.log(format!("failed to set up webhook: {}", e), 14)).ok()
+        }),
+        relabel: config.relabel.iter().map(|r| Relabel { from: s(&r.from), to: s(&r.to) }).collect(),
+        trash: config.trash.iter().map(|x| x.to_filter()).collect(),
+        trash_addr: config.trash_addr.as_ref().map(|x| s(x)),
+        api: match (config.api_port, running.and_then(|x| x.config.api.clone())) {
+            (Some(port), Some(api)) if api.port == port => Some(api),
+            (Some(port), _) => {
+                Some(ApiListener::bind(port).map_err(|e| format!("Failed to listen on api_port {}: {}", port, e))?)
+            }
+            (None, _) => None,
+        },
+        fair_scheduling: config.fair_scheduling,
+        craft_queue_file: config.craft_queue_file.as_ref().map(|x| s(x)),
+        alerts: config
+            .alerts
             .iter()
-            .map(|f| FluidAccess {
-                client: s(&f.client),
-                fluid_bus_addrs: f.fluid_bus_addrs.iter().map(|a| s(a)).collect(),
-                tank_addr: s(&f.tank_addr),
+            .map(|a| AlertRule {
+                item: a.item.to_filter(),
+                n_min: a.min,
+                once: a.once,
             })
             .collect(),
+        fluid_alerts: (config.fluid_alerts.iter())
+            .map(|a| FluidAlertRule { fluid: s(&a.fluid), n_min: a.min, once: a.once })
+            .collect(),
+        clients: client_roles(&config).into_keys().map(|c| s(c)).collect(),
+        turtle_clients: (config.processes.iter())
+            .filter_map(|x| if let ProcessConfig::Turtle { client, .. } = &x.process { Some(s(client)) } else { None })
+            .collect(),
     }
     .build(|factory| {
         // Add storages
-        for storage in &config.storages {
+        for (i, storage) in config.storages.iter().enumerate() {
             match storage {
                 StorageConfig::Chest {
                     accesses,
                     override_max_stack_size,
+                    stack_overrides,
+                    accept,
+                    reject,
+                    min_extract_count,
+                    priority,
+                    weight,
+                    read_only,
+                    extract_only,
+                    insert_only,
                 } => {
                     factory.add_storage(ChestConfig {
                         accesses: accesses
+                            .resolve(&config.access_groups)?
                             .iter()
                             .map(|a| BusAccess {
                                 client: s(&a.client),
@@ -203,11 +600,31 @@ pub fn build_factory_from_json(tui: Rc<Tui>, config_path: &str) -> Rc<RefCell<Fa
                         override_max_stack_size: override_max_stack_size.map(|size| {
                             Box::new(move |_| size) as Box<dyn Fn(i32) -> i32>
                         }),
+                        stack_overrides: stack_overrides.iter().map(|x| (x.item.to_filter(), x.size)).collect(),
+                        min_extract_count: *min_extract_count,
+                        priority: *priority,
+                        weight: storage_weight(i, *weight)?,
+                        accept: accept.iter().map(|f| f.to_filter()).collect(),
+                        reject: reject.iter().map(|f| f.to_filter()).collect(),
+                        allow_deposit: !(read_only | extract_only),
+                        allow_extract: !(read_only | insert_only),
                     });
                 }
-                StorageConfig::Drawer { accesses, filters } => {
+                StorageConfig::Drawer {
+                    accesses,
+                    filters,
+                    min_extract_count,
+                    void,
+                    controller,
+                    priority,
+                    weight,
+                    read_only,
+                    extract_only,
+                    insert_only,
+                } => {
                     factory.add_storage(DrawerConfig {
                         accesses: accesses
+                            .resolve(&config.access_groups)?
                             .iter()
                             .map(|a| BusAccess {
                                 client: s(&a.client),
@@ -216,17 +633,33 @@ pub fn build_factory_from_json(tui: Rc<Tui>, config_path: &str) -> Rc<RefCell<Fa
                             })
                             .collect(),
                         filters: filters.iter().map(|f| f.to_filter()).collect(),
+                        min_extract_count: *min_extract_count,
+                        void: *void,
+                        controller: *controller,
+                        priority: *priority,
+                        weight: storage_weight(i, *weight)?,
+                        allow_deposit: !(read_only | extract_only),
+                        allow_extract: !(read_only | insert_only),
                     });
                 }
             }
         }
 
         // Add processes
-        for process in &config.processes {
-            match process {
-                ProcessConfig::ManualUI { accesses } => {
-                    factory.add_process(ManualUiConfig {
+        for entry in &config.processes {
+            let options = ProcessOptions {
+                name: Some(entry.process.name().into()),
+                ignore_pause: entry.ignore_pause,
+                background: entry.background,
+                disabled: !entry.enabled.unwrap_or(true),
+                interval: entry.interval_secs.map(Duration::from_secs),
+                after: entry.after.iter().map(|x| x.as_str().into()).collect(),
+            };
+            match &entry.process {
+                ProcessConfig::ManualUI { accesses, .. } => {
+                    factory.add_process_with(options, ManualUiConfig {
                         accesses: accesses
+                            .resolve(&config.access_groups)?
                             .iter()
                             .map(|a| BusAccess {
                                 client: s(&a.client),
@@ -236,10 +669,17 @@ pub fn build_factory_from_json(tui: Rc<Tui>, config_path: &str) -> Rc<RefCell<Fa
                             .collect(),
                     });
                 }
-                ProcessConfig::Workbench { name, accesses, recipes } => {
-                    factory.add_process(WorkbenchConfig {
+                ProcessConfig::Workbench { name, accesses, recipes, grid, max_crafts_per_cycle } => {
+                    let mut converted = Vec::new();
+                    for recipe in recipes {
+                        let slots = recipe.grid_slots(*grid).map_err(|e| format!("{}: {}", name, e))?;
+                        converted.push(convert_recipe(factory, name, recipe, &slots))
+                    }
+                    let recipes = converted;
+                    factory.add_process_with(options, WorkbenchConfig {
                         name: s(name),
                         accesses: accesses
+                            .resolve(&config.access_groups)?
                             .iter()
                             .map(|a| BusAccess {
                                 client: s(&a.client),
@@ -247,7 +687,8 @@ pub fn build_factory_from_json(tui: Rc<Tui>, config_path: &str) -> Rc<RefCell<Fa
                                 bus_addr: s(&a.addr),
                             })
                             .collect(),
-                        recipes: recipes.iter().map(convert_recipe).collect(),
+                        recipes,
+                        max_crafts_per_cycle: *max_crafts_per_cycle,
                     });
                 }
                 ProcessConfig::Slotted {
@@ -257,10 +698,21 @@ pub fn build_factory_from_json(tui: Rc<Tui>, config_path: &str) -> Rc<RefCell<Fa
                     extract_filter,
                     recipes,
                     strict_priority,
+                    max_crafts_per_cycle,
                 } => {
-                    factory.add_process(SlottedConfig {
+                    if recipes.iter().any(|x| x.shapeless) {
+                        return Err(format!("{}: shapeless recipes are only supported by Workbench", name));
+                    }
+                    let mut converted = Vec::new();
+                    for recipe in recipes {
+                        let slots: Vec<_> = recipe.inputs.iter().map(|x| x.slots.clone()).collect();
+                        converted.push(convert_recipe(factory, name, recipe, &slots))
+                    }
+                    let recipes = converted;
+                    factory.add_process_with(options, SlottedConfig {
                         name: s(name),
                         accesses: accesses
+                            .resolve(&config.access_groups)?
                             .iter()
                             .map(|a| BusAccess {
                                 client: s(&a.client),
@@ -269,55 +721,138 @@ pub fn build_factory_from_json(tui: Rc<Tui>, config_path: &str) -> Rc<RefCell<Fa
                             })
                             .collect(),
                         input_slots: input_slots.clone(),
-                        to_extract: extract_filter
-                            .as_ref()
-                            .map(|f| Box::new(move |_, _, _| true) as Box<dyn Fn(&Factory, usize, &DetailStack) -> bool>),
-                        recipes: recipes.iter().map(convert_recipe).collect(),
+                        to_extract: extract_filter.as_ref().map(|f| {
+                            let filter = f.to_filter();
+                            Box::new(move |_: &Factory, _, stack: &DetailStack| filter.apply(&stack.item, &stack.detail))
+                                as Box<dyn Fn(&Factory, usize, &DetailStack) -> bool>
+                        }),
+                        recipes,
                         strict_priority: *strict_priority,
+                        max_crafts_per_cycle: *max_crafts_per_cycle,
                     });
                 }
-                ProcessConfig::Turtle { name, file_name, client } => {
+                ProcessConfig::Turtle { name, file_name, client, low_fuel } => {
                     // Turtle processes require special handling since they're more complex
-                    factory.add_process(TurtleConfig {
+                    factory.add_process_with(options, TurtleConfig {
                         name: s(name),
                         file_name: s(file_name),
                         client: s(client),
+                        low_fuel: low_fuel.unwrap_or(1000),
                         program: Box::new(|_, _| async {}),
                     });
                 }
-                ProcessConfig::RedstoneEmitter { accesses, output_rules } => {
+                ProcessConfig::RedstoneEmitter { accesses, output_rules, .. } => {
+                    let accesses = accesses.resolve(&config.access_groups)?;
+                    let outputs = || -> Vec<RedstoneAccess> {
+                        accesses
+                            .iter()
+                            .map(|a| RedstoneAccess {
+                                client: s(&a.client),
+                                addr: s(&a.addr),
+                            })
+                            .collect()
+                    };
                     for rule in output_rules {
-                        factory.add_process(RedstoneEmitterConfig {
-                            accesses: accesses
-                                .iter()
-                                .map(|a| RedstoneAccess {
-                                    client: s(&a.client),
-                                    addr: s(&a.addr),
-                                })
-                                .collect(),
-                            output: Box::new(move |factory| {
-                                // Implement redstone logic based on output rules
-                                rule.off_signal
-                            }),
+                        if let Some(fill) = &rule.trigger_fill {
+                            factory.add_process_with(options.clone(), FillEmitterConfig {
+                                name: s(&rule.name),
+                                accesses: vec![BasicAccess { client: s(&fill.client), addr: s(&fill.addr) }],
+                                outputs: outputs(),
+                                threshold: fill.percent / 100.,
+                                on: rule.on_signal,
+                                off: rule.off_signal,
+                            });
+                            continue;
+                        }
+                        factory.add_process_with(options.clone(), RedstoneEmitterConfig {
+                            accesses: outputs(),
+                            output: emit_when_stored(
+                                s(&rule.name),
+                                rule.off_signal,
+                                rule.on_signal,
+                                rule.trigger_items.iter().map(|x| x.to_filter()).collect(),
+                            ),
+                            off: rule.off_signal,
                         });
                     }
                 }
+                ProcessConfig::Import { accesses, filters, .. } => {
+                    factory.add_process_with(options, ImportConfig {
+                        accesses: accesses
+                            .resolve(&config.access_groups)?
+                            .iter()
+                            .map(|a| BusAccess {
+                                client: s(&a.client),
+                                inv_addr: s(&a.addr),
+                                bus_addr: s(&a.addr),
+                            })
+                            .collect(),
+                        filters: filters.iter().map(|x| x.to_filter()).collect(),
+                    });
+                }
+                ProcessConfig::Export { name, accesses, targets } => {
+                    factory.add_process_with(options, ExportConfig {
+                        name: s(name),
+                        accesses: accesses
+                            .resolve(&config.access_groups)?
+                            .iter()
+                            .map(|a| BusAccess {
+                                client: s(&a.client),
+                                inv_addr: s(&a.addr),
+                                bus_addr: s(&a.addr),
+                            })
+                            .collect(),
+                        targets: (targets.iter())
+                            .map(|x| ExportTarget { item: x.item.to_filter(), slot: x.slot, n_wanted: x.count })
+                            .collect(),
+                    });
+                }
             }
         }
-    })
+        Ok(())
+    })?;
+    // Only switch logs once the build has succeeded, so a failed reload keeps logging where it did.
+    if let Some(log_file) = log_file {
+        tui.set_log_file(log_file)
+    }
+    Ok(factory)
 }
 
-fn convert_recipe(recipe: &CraftingRecipe) -> CraftingGridRecipe {
+// `slots` holds the grid slots of each input, as laid out by grid_slots.
+fn convert_recipe(
+    factory: &mut Factory,
+    process: &str,
+    recipe: &CraftingRecipe,
+    slots: &[Vec<SlotConfig>],
+) -> CraftingGridRecipe {
+    let outputs: Vec<Filter> = recipe.outputs.iter().map(|x| x.to_filter()).collect();
+    factory.add_to_recipe_book(RecipeBookEntry {
+        process: s(process),
+        outputs: outputs.clone(),
+        inputs: (recipe.inputs.iter().zip(slots))
+            .map(|(input, slots)| (input.item.to_filter(), slots.iter().map(|s| s.size).sum()))
+            .collect(),
+    });
+    let mut vetoes: Vec<_> =
+        recipe.byproducts.iter().map(|x| Byproduct::new(x.item.to_filter(), x.max_stored)).collect();
+    if let Some(n_max) = recipe.max_stock {
+        // A capped output vetoes the craft the same way a byproduct at its limit does.
+        vetoes.extend(outputs.iter().map(|x| Byproduct::new(x.clone(), n_max)))
+    }
     CraftingGridRecipe {
-        outputs: Rc::new(Vec::new()), // Need proper output conversion
-        inputs: recipe
-            .inputs
-            .iter()
+        outputs: vetoes.into_iter().fold(
+            (outputs.into_iter())
+                .map(|filter| Output::new(filter, recipe.n_wanted))
+                .reduce(BoxedOutputs::and)
+                .unwrap_or_else(|| ignore_outputs(0.)),
+            BoxedOutputs::or,
+        ),
+        inputs: (recipe.inputs.iter().zip(slots))
             .map(
-                |input| SlottedInput {
+                |(input, slots)| SlottedInput {
                     item: input.item.to_filter(),
-                    size: input.slots.iter().map(|s| s.size).sum(),
-                    slots: input.slots.iter().map(|s| (s.slot, s.size)).collect(),
+                    size: slots.iter().map(|s| s.size).sum(),
+                    slots: slots.iter().map(|s| (s.slot, s.size)).collect(),
                     allow_backup: input.allow_backup,
                     extra_backup: input.extra_backup,
                 },
@@ -325,37 +860,467 @@ fn convert_recipe(recipe: &CraftingRecipe) -> CraftingGridRecipe {
             .collect(),
         max_sets: recipe.max_sets,
         non_consumables: Vec::new(),
+        priority: recipe.priority,
+    }
+}
+
+// Lists from included files are appended and objects merged key by key; otherwise the including file wins.
+fn merge_config(into: &mut Value, from: Value) {
+    match (into, from) {
+        (Value::Object(into), Value::Object(from)) => {
+            for (key, value) in from {
+                match into.get_mut(&key) {
+                    Some(existing) => merge_config(existing, value),
+                    None => drop(into.insert(key, value)),
+                }
+            }
+        }
+        (Value::Array(into), Value::Array(from)) => into.extend(from),
+        _ => (),
+    }
+}
+
+// Expands `${VAR}`, or `${VAR:-default}` for a variable that may be unset.
+fn expand_env(x: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = x;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let len = rest[start..].find('}').ok_or_else(|| format!("Unterminated ${{ in {:?}", x))?;
+        let expr = &rest[start + 2..start + len];
+        let (name, default) = match expr.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expr, None),
+        };
+        match (std::env::var(name), default) {
+            (Ok(value), _) => result.push_str(&value),
+            (Err(_), Some(default)) => result.push_str(default),
+            (Err(_), None) => return Err(format!("Undefined environment variable {} in {:?}", name, x)),
+        }
+        rest = &rest[start + len + 1..]
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn substitute_env(value: &mut Value) -> Result<(), String> {
+    match value {
+        Value::String(x) => *x = expand_env(x)?,
+        Value::Array(x) => x.iter_mut().try_for_each(substitute_env)?,
+        Value::Object(x) => x.values_mut().try_for_each(substitute_env)?,
+        _ => (),
+    }
+    Ok(())
+}
+
+fn load_config_value(path: &Path, stack: &mut Vec<PathBuf>, files: &mut Vec<PathBuf>) -> Result<Value, String> {
+    let display = path.display();
+    let canonical = path.canonicalize().map_err(|e| format!("Failed to read config file {}: {}", display, e))?;
+    if stack.contains(&canonical) {
+        return Err(format!("Include cycle through {}", display));
+    }
+    let content = fs::read_to_string(path).map_err(|e| format!("Failed to read config file {}: {}", display, e))?;
+    let mut value: Value =
+        serde_json::from_str(&content).map_err(|e| format!("Failed to parse config file {}: {}", display, e))?;
+    substitute_env(&mut value).map_err(|e| format!("{}: {}", display, e))?;
+    let includes = value.as_object_mut().and_then(|x| x.remove("includes"));
+    let includes: Vec<String> = match includes {
+        Some(includes) => serde_json::from_value(includes).map_err(|e| format!("{}: invalid includes: {}", display, e))?,
+        None => Vec::new(),
+    };
+    stack.push(canonical.clone());
+    files.push(canonical);
+    let dir = path.parent().unwrap_or(Path::new("."));
+    for include in includes {
+        let included = load_config_value(&dir.join(include), stack, files)?;
+        merge_config(&mut value, included)
+    }
+    stack.pop();
+    Ok(value)
+}
+
+// Also returns every file the config was read from, includes and all.
+// A directory stands for the `config.json` inside it.
+pub fn try_load_dynamic_config_with_files(path: &str) -> Result<(DynamicFactoryConfig, Vec<PathBuf>), String> {
+    let mut path = PathBuf::from(path);
+    if path.is_dir() {
+        path.push("config.json")
+    }
+    let mut files = Vec::new();
+    let value = load_config_value(&path, &mut Vec::new(), &mut files)?;
+    let config = serde_json::from_value(value).map_err(|e| format!("Failed to parse config file: {}", e))?;
+    Ok((config, files))
+}
+
+pub fn try_load_dynamic_config(path: &str) -> Result<DynamicFactoryConfig, String> {
+    try_load_dynamic_config_with_files(path).map(|(config, _)| config)
+}
+
+fn clients_of<'a>(config: &'a DynamicFactoryConfig, accesses: &'a AccessesConfig) -> impl Iterator<Item = &'a str> {
+    let accesses: &[BusAccessConfig] = match accesses {
+        AccessesConfig::List(accesses) => accesses,
+        AccessesConfig::Group(name) => config.access_groups.get(name).map_or(&[][..], Vec::as_slice),
+    };
+    accesses.iter().map(|x| &*x.client)
+}
+
+// Every client the config refers to, with the roles it is referred to in.
+fn client_roles(config: &DynamicFactoryConfig) -> BTreeMap<&str, BTreeSet<&'static str>> {
+    let mut found = Vec::new();
+    found.extend(config.bus_accesses.iter().map(|x| (&*x.client, "bus")));
+    found.extend(config.fluid_bus_accesses.iter().map(|x| (&*x.client, "fluid bus")));
+    found.extend(config.log_clients.iter().map(|x| (&**x, "log")));
+    for storage in &config.storages {
+        let (StorageConfig::Chest { accesses, .. } | StorageConfig::Drawer { accesses, .. }) = storage;
+        found.extend(clients_of(config, accesses).map(|x| (x, "storage")))
+    }
+    for entry in &config.processes {
+        match &entry.process {
+            ProcessConfig::Turtle { client, .. } => found.push((&**client, "turtle")),
+            ProcessConfig::RedstoneEmitter { accesses, output_rules, .. } => {
+                found.extend(clients_of(config, accesses).map(|x| (x, "process")));
+                found.extend(output_rules.iter().flat_map(|x| &x.trigger_fill).map(|x| (&*x.client, "process")))
+            }
+            ProcessConfig::ManualUI { accesses, .. }
+            | ProcessConfig::Workbench { accesses, .. }
+            | ProcessConfig::Slotted { accesses, .. }
+            | ProcessConfig::Import { accesses, .. }
+            | ProcessConfig::Export { accesses, .. } => {
+                found.extend(clients_of(config, accesses).map(|x| (x, "process")))
+            }
+        }
+    }
+    let mut roles = BTreeMap::<_, BTreeSet<_>>::new();
+    for (client, role) in found {
+        roles.entry(client).or_default().insert(role);
     }
+    roles
 }
 
-pub fn load_dynamic_config(path: &str) -> DynamicFactoryConfig {
-    let content = fs::read_to_string(path).expect("Failed to read config file");
-    serde_json::from_str(&content).expect("Failed to parse config file")
+// Finds the problems building the factory would fail on, plus some it would silently accept.
+// Deposits are shared in proportion to weight, which only works out for a positive one.
+fn storage_weight(i: usize, weight: Option<f64>) -> Result<Option<f64>, String> {
+    match weight {
+        Some(x) if x.is_nan() || x <= 0. => Err(format!("storage #{}: weight must be positive, got {}", i + 1, x)),
+        _ => Ok(weight),
+    }
+}
+
+// Buses are shared by index across accesses, so a bus named by several of them gets the smallest capacity given.
+fn fluid_bus_capacities(config: &DynamicFactoryConfig) -> Vec<i64> {
+    let n_buses = config.fluid_bus_accesses.first().map_or(0, |x| x.fluid_bus_addrs.len());
+    Vec::from_iter((0..n_buses).map(|bus| {
+        (config.fluid_bus_accesses.iter())
+            .filter_map(|x| x.capacities.get(x.fluid_bus_addrs.get(bus)?).copied())
+            .min()
+            .unwrap_or(config.fluid_bus_capacity)
+    }))
+}
+
+pub fn check_config(config: &DynamicFactoryConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut owners = HashMap::<(String, String), String>::new();
+    let mut check_accesses = |owner: String, accesses: &AccessesConfig, problems: &mut Vec<String>| {
+        let accesses = match accesses.resolve(&config.access_groups) {
+            Ok(accesses) => accesses,
+            Err(e) => return problems.push(format!("{}: {}", owner, e)),
+        };
+        for access in accesses {
+            if let Some(other) = owners.insert((access.client.clone(), access.addr.clone()), owner.clone()) {
+                if other != owner {
+                    problems.push(format!("{}: {}/{} is also used by {}", owner, access.client, access.addr, other))
+                }
+            }
+        }
+    };
+    for (i, storage) in config.storages.iter().enumerate() {
+        let (StorageConfig::Chest { accesses, weight, .. } | StorageConfig::Drawer { accesses, weight, .. }) = storage;
+        check_accesses(format!("storage #{}", i + 1), accesses, &mut problems);
+        problems.extend(storage_weight(i, *weight).err())
+    }
+    let names: Vec<_> = config.processes.iter().map(|entry| entry.process.name()).collect();
+    for (i, entry) in config.processes.iter().enumerate() {
+        let name = entry.process.name();
+        if names[..i].contains(&name) {
+            problems.push(format!("{}: duplicate process name", name))
+        }
+        for dep in entry.after.iter().filter(|dep| !names.contains(&dep.as_str())) {
+            problems.push(format!("{}: unknown process {} in after", name, dep))
+        }
+        match &entry.process {
+            ProcessConfig::ManualUI { accesses, .. }
+            | ProcessConfig::RedstoneEmitter { accesses, .. }
+            | ProcessConfig::Import { accesses, .. }
+            | ProcessConfig::Export { accesses, .. } => check_accesses(name.to_owned(), accesses, &mut problems),
+            ProcessConfig::Workbench { accesses, recipes, grid, .. } => {
+                check_accesses(name.to_owned(), accesses, &mut problems);
+                for recipe in recipes {
+                    if let Err(e) = recipe.grid_slots(*grid) {
+                        problems.push(format!("{}: {}", name, e))
+                    }
+                }
+            }
+            ProcessConfig::Slotted { accesses, input_slots, recipes, .. } => {
+                check_accesses(name.to_owned(), accesses, &mut problems);
+                if recipes.iter().any(|x| x.shapeless) {
+                    problems.push(format!("{}: shapeless recipes are only supported by Workbench", name))
+                }
+                for slot in recipes.iter().flat_map(|x| &x.inputs).flat_map(|x| &x.slots) {
+                    if !input_slots.contains(&slot.slot) {
+                        problems.push(format!("{}: recipe slot {} is not in input_slots", name, slot.slot))
+                    }
+                }
+            }
+            ProcessConfig::Turtle { .. } => (),
+        }
+    }
+    let roles = client_roles(config);
+    for (client, roles) in &roles {
+        if roles.contains("turtle") && roles.iter().any(|x| !matches!(*x, "turtle" | "log")) {
+            let roles: Vec<_> = roles.iter().copied().collect();
+            problems.push(format!("client {}: runs a turtle process but is also used as {}", client, roles.join(", ")))
+        }
+    }
+    let mut turtles = HashSet::new();
+    for entry in &config.processes {
+        if let ProcessConfig::Turtle { name, client, .. } = &entry.process {
+            if !turtles.insert(client) {
+                problems.push(format!("{}: client {} already runs another turtle process", name, client))
+            }
+        }
+    }
+    // One computer typed two ways connects as only one of them, leaving the other never connected.
+    let names: Vec<_> = roles.keys().collect();
+    for (i, a) in names.iter().enumerate() {
+        for b in names[i + 1..].iter().filter(|b| a.eq_ignore_ascii_case(b)) {
+            problems.push(format!("clients {} and {} differ only in case", a, b))
+        }
+    }
+    for access in &config.fluid_bus_accesses {
+        for addr in access.capacities.keys().filter(|x| !access.fluid_bus_addrs.contains(x)) {
+            problems.push(format!("fluid_bus_accesses: {} has a capacity for unknown bus {}", access.client, addr))
+        }
+    }
+    if !config.trash.is_empty() && config.trash_addr.is_none() {
+        problems.push("trash is set but trash_addr is missing, so nothing will be trashed".to_owned())
+    }
+    let n_legacy = config.backups.iter().filter(|x| x.amount().is_none()).count();
+    let n_fluid_legacy = config.fluid_backups.iter().filter(|x| x.amount().is_none()).count();
+    for (key, n, shape) in [("backups", n_legacy, "{item, qty}"), ("fluid_backups", n_fluid_legacy, "{fluid, qty}")] {
+        if n > 0 {
+            problems.push(format!("{}: {} entries use the old access shape and are ignored, use {}", key, n, shape))
+        }
+    }
+    problems
 }
 
-pub fn start_factory_hot_reload(
+// Shared by the file watcher and the `reload` command. Replacing the slot drops the old factory, aborting its tasks.
+// Any config error is returned before the swap, so a bad reload leaves the running factory in place.
+pub fn reload_factory(
     tui: Rc<Tui>,
     config_path: &str,
-    factory_ref: Arc<Mutex<Option<Rc<RefCell<Factory>>>>>,
-) {
+    factory_ref: &FactorySlot,
+    running: Option<&Factory>,
+) -> Result<(), String> {
+    let new_factory = build_factory_from_json(tui, config_path, running)?;
+    new_factory.borrow_mut().set_reload(config_path.to_owned(), factory_ref);
+    *factory_ref.lock().unwrap() = Some(new_factory);
+    Ok(())
+}
+
+pub fn build_reloadable_factory(tui: Rc<Tui>, config_path: &str) -> FactorySlot {
+    let factory_ref = FactorySlot::default();
+    reload_factory(tui, config_path, &factory_ref, None).unwrap_or_else(|e| panic!("{}", e));
+    factory_ref
+}
+
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(300);
+
+pub fn start_factory_hot_reload(tui: Rc<Tui>, config_path: &str, factory_ref: FactorySlot) {
     let config_path = config_path.to_string();
     thread::spawn(move || {
         let (tx, rx) = channel();
         let mut watcher: RecommendedWatcher =
             Watcher::new(tx, notify::Config::default()).expect("Failed to create watcher");
-        watcher
-            .watch(config_path.clone(), RecursiveMode::NonRecursive)
-            .expect("Failed to watch config file");
+        let mut watched = HashSet::new();
+        let is_dir = Path::new(&config_path).is_dir();
+        if is_dir {
+            watcher.watch(Path::new(&config_path), RecursiveMode::Recursive).expect("Failed to watch config directory");
+        }
+        // Only the config's own files count, as a directory also holds the detail cache, craft queue and temporary
+        // files the factory writes itself.
+        let watch_files = |watcher: &mut RecommendedWatcher, watched: &mut HashSet<PathBuf>| {
+            let files = try_load_dynamic_config_with_files(&config_path).map(|(_, files)| files).unwrap_or_else(|_| {
+                let path =
+                    if is_dir { Path::new(&config_path).join("config.json") } else { PathBuf::from(&config_path) };
+                vec![path.canonicalize().unwrap_or(path)]
+            });
+            for file in files {
+                if !watched.contains(&file) {
+                    if !is_dir {
+                        watcher.watch(&file, RecursiveMode::NonRecursive).expect("Failed to watch config file");
+                    }
+                    watched.insert(file);
+                }
+            }
+        };
+        watch_files(&mut watcher, &mut watched);
         loop {
             match rx.recv() {
-                Ok(Event { .. }) => {
-                    let new_factory = build_factory_from_json(tui.clone(), &config_path);
-                    let mut factory_lock = factory_ref.lock().unwrap();
-                    *factory_lock = Some(new_factory);
-                    println!("Factory configuration reloaded from JSON.");
+                Ok(Event { paths, .. }) => {
+                    if !paths.iter().any(|x| watched.contains(&x.canonicalize().unwrap_or_else(|_| x.clone()))) {
+                        continue;
+                    }
+                    // A single save can touch several files, or one file several times, so wait for things to settle.
+                    while rx.recv_timeout(RELOAD_DEBOUNCE).is_ok() {}
+                    watch_files(&mut watcher, &mut watched);
+                    let running = factory_ref.lock().unwrap().clone();
+                    let running = running.as_ref().map(|x| x.borrow());
+                    match reload_factory(tui.clone(), &config_path, &factory_ref, running.as_deref()) {
+                        Ok(()) => println!("Factory configuration reloaded from JSON."),
+                        Err(e) => println!("Reload failed: {}", e),
+                    }
                 }
                 Err(e) => println!("Watch error: {:?}", e),
             }
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::factory::tests::{stock, with_test_factory};
+    use serde_json::json;
+    use std::net::TcpListener;
+
+    fn minimal() -> Value {
+        json!({
+            "server_port": 1847,
+            "min_cycle_time_secs": 1,
+            "log_clients": [],
+            "bus_accesses": [{ "client": "main", "addr": "minecraft:chest_0" }],
+            "fluid_bus_accesses": [],
+            "fluid_bus_capacity": 0,
+            "storages": [],
+            "processes": [],
+            "backups": [],
+            "fluid_backups": [],
+        })
+    }
+
+    fn parse(value: Value) -> DynamicFactoryConfig { serde_json::from_value(value).unwrap() }
+
+    #[test]
+    fn backups_accept_old_access_shape() {
+        let mut value = minimal();
+        value["backups"] = json!([
+            { "item": { "type": "Name", "value": "minecraft:coal" }, "qty": 64 },
+            { "client": "main", "addr": "minecraft:chest_1" },
+        ]);
+        value["fluid_backups"] = json!([
+            { "fluid": "minecraft:water", "qty": 1000 },
+            { "client": "main", "fluid_bus_addrs": ["tank_0"], "tank_addr": "tank_1" },
+        ]);
+        let config = parse(value);
+        assert_eq!(config.backups.iter().filter_map(BackupEntry::amount).map(|x| x.qty).collect::<Vec<_>>(), [64]);
+        let fluids: Vec<_> = config.fluid_backups.iter().filter_map(BackupEntry::amount).map(|x| x.qty).collect();
+        assert_eq!(fluids, [1000]);
+        let problems = check_config(&config);
+        assert!(problems.iter().any(|x| x.starts_with("backups: 1 entries")), "{problems:?}");
+        assert!(problems.iter().any(|x| x.starts_with("fluid_backups: 1 entries")), "{problems:?}");
+    }
+
+    #[test]
+    fn non_positive_storage_weight_is_a_problem() {
+        let mut value = minimal();
+        value["storages"] = json!([
+            { "type": "Chest", "accesses": [{ "client": "main", "addr": "minecraft:chest_1" }], "weight": 2.5 },
+            { "type": "Chest", "accesses": [{ "client": "main", "addr": "minecraft:chest_2" }], "weight": 0 },
+        ]);
+        assert_eq!(check_config(&parse(value)), ["storage #2: weight must be positive, got 0"]);
+    }
+
+    #[test]
+    fn max_stock_halts_recipe_at_cap() {
+        with_test_factory(|factory, _| async move {
+            let recipe: CraftingRecipe = serde_json::from_value(json!({
+                "outputs": [{ "type": "Name", "value": "minecraft:iron_block" }],
+                "inputs": [{
+                    "item": { "type": "Name", "value": "minecraft:iron_ingot" },
+                    "slots": [{ "slot": 0, "size": 9 }],
+                    "allow_backup": false,
+                    "extra_backup": 0,
+                }],
+                "max_sets": 1,
+                "n_wanted": 1000,
+                "max_stock": 64,
+            }))
+            .unwrap();
+            let mut factory = factory.borrow_mut();
+            stock(&mut factory, "minecraft:iron_ingot", 90);
+            let slots: Vec<_> = recipe.inputs.iter().map(|x| x.slots.clone()).collect();
+            let recipes = [convert_recipe(&mut factory, "workbench", &recipe, &slots)];
+            stock(&mut factory, "minecraft:iron_block", 63);
+            assert_eq!(compute_demands(&factory, &recipes).len(), 1);
+            stock(&mut factory, "minecraft:iron_block", 1);
+            assert!(compute_demands(&factory, &recipes).is_empty());
+        })
+    }
+
+    #[test]
+    fn reload_keeps_listening_on_the_same_ports() {
+        with_test_factory(|factory, tui| async move {
+            let (server_port, api_port) = {
+                let (a, b) = (TcpListener::bind("[::]:0").unwrap(), TcpListener::bind("[::]:0").unwrap());
+                (a.local_addr().unwrap().port(), b.local_addr().unwrap().port())
+            };
+            factory.borrow().config.server.borrow_mut().listen(server_port).unwrap();
+            factory.borrow_mut().config.api = Some(ApiListener::bind(api_port).unwrap());
+            let slot = FactorySlot::default();
+            *slot.lock().unwrap() = Some(factory);
+
+            let mut value = minimal();
+            value["server_port"] = json!(server_port);
+            value["api_port"] = json!(api_port);
+            let path = std::env::temp_dir().join(format!("reload_{}.json", std::process::id()));
+            fs::write(&path, value.to_string()).unwrap();
+            for _ in 0..2 {
+                let running = slot.lock().unwrap().clone().unwrap();
+                reload_factory(tui.clone(), path.to_str().unwrap(), &slot, Some(&running.borrow())).unwrap();
+                let (running, reloaded) = (running.borrow(), slot.lock().unwrap().clone().unwrap());
+                let reloaded = reloaded.borrow();
+                assert!(Rc::ptr_eq(&running.config.server, &reloaded.config.server));
+                assert!(Rc::ptr_eq(running.config.api.as_ref().unwrap(), reloaded.config.api.as_ref().unwrap()));
+            }
+            fs::remove_file(path).unwrap();
+        })
+    }
+
+    #[test]
+    fn stored_byproduct_halts_recipe() {
+        with_test_factory(|factory, _| async move {
+            let recipe: CraftingRecipe = serde_json::from_value(json!({
+                "outputs": [{ "type": "Name", "value": "minecraft:iron_plate" }],
+                "byproducts": [{ "item": { "type": "Name", "value": "minecraft:iron_nugget" }, "max_stored": 32 }],
+                "inputs": [{
+                    "item": { "type": "Name", "value": "minecraft:iron_ingot" },
+                    "slots": [{ "slot": 0, "size": 1 }],
+                    "allow_backup": false,
+                    "extra_backup": 0,
+                }],
+                "max_sets": 1,
+                "n_wanted": 64,
+            }))
+            .unwrap();
+            let mut factory = factory.borrow_mut();
+            stock(&mut factory, "minecraft:iron_ingot", 10);
+            let slots: Vec<_> = recipe.inputs.iter().map(|x| x.slots.clone()).collect();
+            let recipes = [convert_recipe(&mut factory, "press", &recipe, &slots)];
+            stock(&mut factory, "minecraft:iron_nugget", 31);
+            assert_eq!(compute_demands(&factory, &recipes).len(), 1);
+            stock(&mut factory, "minecraft:iron_nugget", 1);
+            assert!(compute_demands(&factory, &recipes).is_empty());
+        })
+    }
+}
@@ -1,9 +1,10 @@
-use super::item::{Detail, Filter, Item};
+use super::item::{normalize_label, Detail, Filter, Item};
 use flexstr::LocalStr;
 use std::rc::Rc;
 
 pub fn s(x: &'static str) -> LocalStr { LocalStr::from_static(x) }
 pub fn label(x: &'static str) -> Filter { Filter::Label(s(x)) }
+pub fn label_fuzzy(x: &str) -> Filter { Filter::LabelFuzzy(normalize_label(x).into()) }
 pub fn name(x: &'static str) -> Filter { Filter::Name(s(x)) }
 pub fn both(label: &'static str, name: &'static str) -> Filter { Filter::Both { label: s(label), name: s(name) } }
 
@@ -11,6 +12,36 @@ pub fn custom(desc: &'static str, func: impl Fn(&Item, &Detail) -> bool + 'stati
     Filter::Custom { desc: s(desc), func: Rc::new(func) }
 }
 
+// `*` matches any run of characters and `\*` is a literal asterisk; without wildcards this is a plain Name filter.
+pub fn name_glob(pattern: &str) -> Filter {
+    let mut parts = vec![String::new()];
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.as_str().starts_with('*') => parts.last_mut().unwrap().extend(chars.next()),
+            '*' => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+    if parts.len() == 1 {
+        return Filter::Name(parts.pop().unwrap().into());
+    }
+    let func = move |item: &Item, _: &Detail| glob_match(&parts, &item.name);
+    Filter::Custom { desc: pattern.to_owned().into(), func: Rc::new(func) }
+}
+
+// `parts` are the literal runs between wildcards, so there are always at least two of them.
+fn glob_match(parts: &[String], text: &str) -> bool {
+    let (first, rest) = parts.split_first().unwrap();
+    let (last, middle) = rest.split_last().unwrap();
+    let Some(mut text) = text.strip_prefix(first.as_str()) else { return false };
+    for part in middle {
+        let Some(pos) = text.find(part.as_str()) else { return false };
+        text = &text[pos + part.len()..]
+    }
+    text.ends_with(last.as_str())
+}
+
 macro_rules! label {
     ($($t:tt)*) => {
         Filter::Label(local_fmt!($($t)*))
@@ -22,3 +53,56 @@ macro_rules! name {
         Filter::Name(local_fmt!($($t)*))
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::lua_value::Table;
+    use super::*;
+
+    fn accepts(pattern: &str, name: &str) -> bool {
+        let item = Item {
+            name: name.to_owned().into(),
+            nbt_hash: None,
+            #[cfg(feature = "plethora")]
+            damage: 0,
+        };
+        let detail = Detail { label: <_>::default(), max_size: 64, others: Table::new() };
+        name_glob(pattern).apply(&item, &detail)
+    }
+
+    #[test]
+    fn glob_without_wildcard_is_exact() {
+        assert!(matches!(name_glob("minecraft:stone"), Filter::Name(_)));
+        assert!(accepts("minecraft:stone", "minecraft:stone"));
+        assert!(!accepts("minecraft:stone", "minecraft:stone_bricks"));
+    }
+
+    #[test]
+    fn glob_wildcard_at_start() {
+        assert!(accepts("*:iron_ingot", "minecraft:iron_ingot"));
+        assert!(accepts("*:iron_ingot", ":iron_ingot"));
+        assert!(!accepts("*:iron_ingot", "minecraft:iron_ingot_block"));
+    }
+
+    #[test]
+    fn glob_wildcard_in_middle() {
+        assert!(accepts("minecraft:*_log", "minecraft:oak_log"));
+        assert!(accepts("a*a", "aa"));
+        assert!(!accepts("a*a", "a"));
+        assert!(!accepts("minecraft:*_log", "create:oak_log"));
+    }
+
+    #[test]
+    fn glob_wildcard_at_end() {
+        assert!(accepts("mekanism:*", "mekanism:ingot_osmium"));
+        assert!(accepts("mekanism:*", "mekanism:"));
+        assert!(!accepts("mekanism:*", "mekanismtools:ingot"));
+    }
+
+    #[test]
+    fn glob_escaped_asterisk_is_literal() {
+        assert!(matches!(name_glob("odd\\*name"), Filter::Name(x) if &*x == "odd*name"));
+        assert!(accepts("odd\\**", "odd*name"));
+        assert!(!accepts("odd\\**", "oddname"));
+    }
+}
@@ -2,30 +2,42 @@ use crate::access::{BasicAccess, FluidAccess, GetClient, TankAccess};
 use crate::action::{ActionFuture, Call, Log};
 use crate::detail_cache::DetailCache;
 use crate::inventory::{list_inventory, Inventory};
-use crate::item::{Detail, DetailStack, Filter, Item};
-use crate::lua_value::{call_result, table_remove, try_into_integer, Key, Table};
+use crate::item::{relabel, Detail, DetailStack, Filter, Item, Relabel};
+use crate::lua_value::{call_result, table_remove, try_into_integer, Key, Table, TransferResult};
 use crate::process::{IntoProcess, Process};
+use crate::recipe::RecipeBookEntry;
 use crate::storage::{DepositResult, Extractor, IntoStorage, Provider, Storage};
-use crate::util::{alive, join_outputs, join_tasks, make_local_one_shot, spawn, LocalReceiver, LocalSender};
-use crate::{server::Server, Tui};
+use crate::util::{
+    alive, jitter, join_outputs, join_tasks, make_local_one_shot, spawn, write_json_atomic, LocalReceiver, LocalSender,
+};
+use crate::{api, command, config::reload_factory, server::Server, statsd::StatsD, webhook::Webhook, Tui};
 use abort_on_drop::ChildTask;
 use flexstr::{local_fmt, local_str, LocalStr};
 use fnv::{FnvHashMap, FnvHashSet};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     cmp::{max, min},
     collections::{hash_map::Entry, BTreeMap, BinaryHeap, VecDeque},
+    fs::File,
     future::Future,
+    io::BufReader,
     mem::take,
     rc::{Rc, Weak},
+    sync::{self, Arc, Mutex},
     time::Duration,
 };
-use tokio::time::{sleep_until, Instant};
+use tokio::time::{sleep, sleep_until, Instant};
+
+// Holds the running factory so a reload can swap in a rebuilt one.
+pub type FactorySlot = Arc<Mutex<Option<Rc<RefCell<Factory>>>>>;
 
 pub struct ItemInfo {
     pub detail: Rc<Detail>,
     pub n_stored: i32,
     n_backup: i32,
+    n_locked: i32,
     providers: BinaryHeap<Provider>,
 }
 
@@ -38,8 +50,13 @@ impl ItemInfo {
         }
     }
 
+    pub fn provide_locked(&mut self, n: i32) {
+        self.n_stored += n;
+        self.n_locked += n
+    }
+
     pub fn get_availability(&self, allow_backup: bool, extra_backup: i32) -> i32 {
-        let mut result = self.n_stored - extra_backup;
+        let mut result = self.n_stored - self.n_locked - extra_backup;
         if !allow_backup {
             result -= self.n_backup;
         }
@@ -114,12 +131,49 @@ pub struct FactoryConfig {
     pub detail_cache: Rc<RefCell<DetailCache>>,
     pub server: Rc<RefCell<Server>>,
     pub min_cycle_time: Duration,
+    // With adaptive timing, idle cycles stretch the interval up to this and busy ones reset it to min_cycle_time.
+    pub max_cycle_time: Option<Duration>,
+    pub cycle_jitter: Duration,
     pub log_clients: Vec<LocalStr>,
     pub bus_accesses: Vec<BasicAccess>,
     pub fluid_bus_accesses: Vec<FluidAccess>,
-    pub fluid_bus_capacity: i64,
+    // One per bus, in the same order as each access's fluid_bus_addrs.
+    pub fluid_bus_capacities: Vec<i64>,
     pub backups: Vec<(Filter, i32)>,
     pub fluid_backups: Vec<(LocalStr, i64)>,
+    pub warn_transfer_size: Option<i32>,
+    pub bus_stuck_cycles: Option<usize>,
+    pub stall_cycles: Option<usize>,
+    pub status_interval: Option<Duration>,
+    pub background_budget: f64,
+    pub statsd: Option<StatsD>,
+    pub webhook: Option<Webhook>,
+    pub relabel: Vec<Relabel>,
+    // Items matching these are pushed from the bus into trash_addr instead of storage.
+    pub trash: Vec<Filter>,
+    pub trash_addr: Option<LocalStr>,
+    pub api: Option<Rc<api::ApiListener>>,
+    pub fair_scheduling: bool,
+    pub craft_queue_file: Option<LocalStr>,
+    pub alerts: Vec<AlertRule>,
+    pub fluid_alerts: Vec<FluidAlertRule>,
+    // Every client the config refers to; any still missing after CONNECT_GRACE gets a warning.
+    pub clients: Vec<LocalStr>,
+    // Clients running a turtle process, the only ones the turtle command evaluates on.
+    pub turtle_clients: Vec<LocalStr>,
+}
+
+pub struct AlertRule {
+    pub item: Filter,
+    pub n_min: i32,
+    // Warn only once per shortage instead of again whenever the level drops further.
+    pub once: bool,
+}
+
+pub struct FluidAlertRule {
+    pub fluid: LocalStr,
+    pub n_min: i64,
+    pub once: bool,
 }
 
 pub struct FluidStorageConfig {
@@ -136,12 +190,36 @@ struct FluidStorage {
     n_stored_lo: i64,
 }
 
+#[derive(Clone, Default)]
+pub struct ProcessOptions {
+    pub name: Option<LocalStr>,
+    pub ignore_pause: bool,
+    pub background: bool,
+    pub disabled: bool,
+    pub interval: Option<Duration>,
+    pub after: Vec<LocalStr>,
+}
+
+struct ProcessEntry {
+    process: Rc<RefCell<dyn Process>>,
+    options: ProcessOptions,
+    last_run: Cell<Option<Instant>>,
+    // Set when the process runs to whether it had craftable demand at the time.
+    pending: Cell<Option<bool>>,
+    n_stalled: Cell<usize>,
+}
+
 pub struct Factory {
     weak: Weak<RefCell<Factory>>,
     _task: ChildTask<Result<(), LocalStr>>,
+    _command_handler: ChildTask<()>,
+    _api: Option<ChildTask<()>>,
+    _connect_check: ChildTask<()>,
     pub config: FactoryConfig,
     storages: Vec<Rc<RefCell<dyn Storage>>>,
-    processes: Vec<Rc<RefCell<dyn Process>>>,
+    // Items deposited into each storage so far, used to spread deposits by weight.
+    n_deposited: Vec<Cell<i64>>,
+    processes: Vec<ProcessEntry>,
     fluid_storages: Vec<Rc<RefCell<FluidStorage>>>,
 
     pub items: FnvHashMap<Rc<Item>, RefCell<ItemInfo>>,
@@ -155,26 +233,120 @@ pub struct Factory {
     bus_free_queue: Vec<usize>,
     bus_size: Option<usize>,
     n_bus_updates: usize,
+    bus_contents: FnvHashMap<usize, (LocalStr, i32)>,
+    n_trashed: RefCell<BTreeMap<LocalStr, i32>>,
+    bus_stuck: FnvHashMap<usize, usize>,
+    // Items on the bus that no storage accepts, already warned about.
+    bus_rejected: RefCell<FnvHashSet<Rc<Item>>>,
 
     fluid_bus_task: Option<ChildTask<Result<(), LocalStr>>>,
     fluid_bus_allocations: FnvHashSet<usize>,
-    fluid_bus_wait_queue: VecDeque<LocalSender<usize>>,
+    // Each waiter with the capacity it needs.
+    fluid_bus_wait_queue: VecDeque<(i64, LocalSender<usize>)>,
     fluid_bus_free_queue: Vec<usize>,
     n_fluid_bus_updates: usize,
+
+    last_cycle_time: Option<Duration>,
+    cycle_interval: Duration,
+    started: Instant,
+    pub stats: Stats,
+    paused: bool,
+    estopped: bool,
+    estop_pending: bool,
+    n_reservations: Cell<usize>,
+    reserved_by: RefCell<FnvHashSet<LocalStr>>,
+    background_credit: f64,
+    rotation: usize,
+    turtle_fuel: BTreeMap<LocalStr, i32>,
+    snapshot_path: Option<String>,
+    reload: Option<(String, sync::Weak<Mutex<Option<Rc<RefCell<Factory>>>>>)>,
+    alert_levels: Vec<Option<i32>>,
+    fluid_alert_levels: Vec<Option<i64>>,
+    recipe_book: Vec<RecipeBookEntry>,
+    craft_requests: Vec<CraftRequest>,
+}
+
+struct CraftRequest {
+    item: Filter,
+    n_target: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedCraftRequest {
+    item: String,
+    n_target: i32,
+}
+
+const N_RECENT_CYCLES: usize = 20;
+const CONNECT_GRACE: Duration = Duration::from_secs(60);
+
+pub struct Stats {
+    pub since: Instant,
+    pub n_cycles: usize,
+    pub n_bus_updates: usize,
+    pub n_fluid_bus_updates: usize,
+    // Items reserved out of or deposited into storage.
+    pub n_items_moved: Cell<i64>,
+    pub recent_cycle_times: VecDeque<Duration>,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Self {
+            since: Instant::now(),
+            n_cycles: 0,
+            n_bus_updates: 0,
+            n_fluid_bus_updates: 0,
+            n_items_moved: Cell::new(0),
+            recent_cycle_times: VecDeque::new(),
+        }
+    }
+
+    fn add_moved(&self, n: i32) { self.n_items_moved.set(self.n_items_moved.get() + n as i64) }
+}
+
+pub struct CycleStatus {
+    pub last_cycle_time: Option<Duration>,
+    pub min_cycle_time: Duration,
+    pub cycle_interval: Duration,
+    pub n_clients: usize,
+    pub paused: bool,
+}
+
+// Mirrors ItemInfo::get_availability: the backup level is held back unless the input allows dipping into it.
+fn fluid_availability(n_stored: i64, n_backup: i64, allow_backup: bool, extra_backup: i64) -> i64 {
+    let mut n_available = n_stored - extra_backup;
+    if !allow_backup {
+        n_available -= n_backup
+    }
+    n_available.max(0)
 }
 
 impl FactoryConfig {
-    pub fn build(self, builder: impl FnOnce(&mut Factory)) -> Rc<RefCell<Factory>> {
+    // All cycle timing goes through tokio::time, so tests can pause the clock and advance it by hand.
+    // A builder error drops the half-built factory, aborting anything it already spawned.
+    pub fn build(
+        self,
+        builder: impl FnOnce(&mut Factory) -> Result<(), String>,
+    ) -> Result<Rc<RefCell<Factory>>, String> {
         let mut fluid_backups = FnvHashMap::default();
         for (fluid, qty) in &self.fluid_backups {
             *fluid_backups.entry(fluid.clone()).or_default() += qty
         }
-        Rc::new_cyclic(|weak| {
+        let alert_levels = vec![None; self.alerts.len()];
+        let fluid_alert_levels = vec![None; self.fluid_alerts.len()];
+        let cycle_interval = self.min_cycle_time;
+        let mut result = Ok(());
+        let factory = Rc::new_cyclic(|weak| {
             let mut factory = Factory {
                 weak: weak.clone(),
                 _task: spawn(factory_main(weak.clone())),
+                _command_handler: command::run(self.tui.clone(), weak.clone()),
+                _api: self.api.clone().map(|listener| api::run(listener, weak.clone())),
+                _connect_check: spawn(check_connected(weak.clone())),
                 config: self,
                 storages: Vec::new(),
+                n_deposited: Vec::new(),
                 processes: Vec::new(),
                 fluid_storages: Vec::new(),
 
@@ -189,16 +361,44 @@ impl FactoryConfig {
                 bus_free_queue: Vec::new(),
                 bus_size: None,
                 n_bus_updates: 0,
+                bus_contents: FnvHashMap::default(),
+                n_trashed: RefCell::new(BTreeMap::new()),
+                bus_stuck: FnvHashMap::default(),
+                bus_rejected: RefCell::default(),
 
                 fluid_bus_task: None,
                 fluid_bus_allocations: FnvHashSet::default(),
                 fluid_bus_wait_queue: VecDeque::new(),
                 fluid_bus_free_queue: Vec::new(),
                 n_fluid_bus_updates: 0,
+
+                last_cycle_time: None,
+                cycle_interval,
+                started: Instant::now(),
+                stats: Stats::new(),
+                paused: false,
+                estopped: false,
+                estop_pending: false,
+                n_reservations: Cell::new(0),
+                reserved_by: RefCell::default(),
+                background_credit: 0.,
+                rotation: 0,
+                turtle_fuel: BTreeMap::new(),
+                snapshot_path: None,
+                reload: None,
+                alert_levels,
+                fluid_alert_levels,
+                recipe_book: Vec::new(),
+                craft_requests: Vec::new(),
             };
-            builder(&mut factory);
+            result = builder(&mut factory);
+            if result.is_ok() {
+                factory.sort_processes();
+                factory.load_craft_requests()
+            }
             RefCell::new(factory)
-        })
+        });
+        result.map(|()| factory)
     }
 }
 
@@ -213,8 +413,174 @@ impl Inventory for Factory {
 }
 
 impl Factory {
-    pub fn add_storage(&mut self, storage: impl IntoStorage) { self.storages.push(storage.into_storage(self)) }
-    pub fn add_process(&mut self, process: impl IntoProcess) { self.processes.push(process.into_process(self)) }
+    pub fn add_storage(&mut self, storage: impl IntoStorage) {
+        self.storages.push(storage.into_storage(self));
+        self.n_deposited.push(Cell::new(0))
+    }
+    pub fn add_process(&mut self, process: impl IntoProcess) {
+        self.add_process_with(ProcessOptions::default(), process)
+    }
+    pub fn add_process_with(&mut self, options: ProcessOptions, process: impl IntoProcess) {
+        let process = process.into_process(self);
+        self.processes.push(ProcessEntry {
+            process,
+            options,
+            last_run: Cell::new(None),
+            pending: Cell::new(None),
+            n_stalled: Cell::new(0),
+        })
+    }
+
+    // Processes that others run after go first, so they get the first pick of each cycle's items.
+    fn sort_processes(&mut self) {
+        let mut pending = take(&mut self.processes);
+        let names: FnvHashSet<_> = pending.iter().filter_map(|entry| entry.options.name.clone()).collect();
+        for entry in &pending {
+            for dep in entry.options.after.iter().filter(|dep| !names.contains(*dep)) {
+                let name = entry.options.name.as_deref().unwrap_or("?");
+                self.log(Log { text: local_fmt!("{}: unknown process {} in after", name, dep), color: 1 })
+            }
+        }
+        while !pending.is_empty() {
+            let is_pending = |dep: &LocalStr| pending.iter().any(|entry| entry.options.name.as_ref() == Some(dep));
+            if let Some(i) = pending.iter().position(|entry| !entry.options.after.iter().any(is_pending)) {
+                self.processes.push(pending.remove(i))
+            } else {
+                let mut names: Vec<_> = pending.iter().filter_map(|entry| entry.options.name.clone()).collect();
+                names.sort();
+                names.dedup();
+                let text = local_fmt!("dependency cycle among processes: {}", names.join(", "));
+                self.log(Log { text, color: 14 });
+                self.processes.append(&mut pending)
+            }
+        }
+    }
+
+    // Why the named process isn't being run at the moment, if it isn't.
+    pub fn process_held_by(&self, name: &str) -> Option<&'static str> {
+        let entry = self.processes.iter().find(|entry| entry.options.name.as_deref() == Some(name))?;
+        if entry.options.disabled {
+            Some("disabled")
+        } else if self.paused && !entry.options.ignore_pause {
+            Some("paused")
+        } else {
+            None
+        }
+    }
+
+    pub fn set_process_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        let mut found = false;
+        for entry in &mut self.processes {
+            if entry.options.name.as_deref() == Some(name) {
+                entry.options.disabled = !enabled;
+                found = true
+            }
+        }
+        found
+    }
+
+    pub fn get_turtle_fuel(&self) -> &BTreeMap<LocalStr, i32> { &self.turtle_fuel }
+    pub fn update_turtle_fuel(&mut self, name: &LocalStr, level: i32, low_fuel: i32) {
+        let old = self.turtle_fuel.insert(name.clone(), level);
+        if level <= 0 {
+            if old.map_or(true, |old| old > 0) {
+                self.log(Log { text: local_fmt!("{}: out of fuel", name), color: 14 })
+            }
+        } else if level < low_fuel && old.map_or(true, |old| old >= low_fuel) {
+            self.log(Log { text: local_fmt!("{}: low on fuel ({})", name, level), color: 1 })
+        }
+    }
+
+    pub fn reset_stats(&mut self) { self.stats = Stats::new() }
+    pub fn is_paused(&self) -> bool { self.paused }
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused != paused {
+            self.paused = paused;
+            let text = if paused { local_str!("factory paused") } else { local_str!("factory resumed") };
+            self.log(Log { text, color: 1 })
+        }
+    }
+
+    pub fn is_estopped(&self) -> bool { self.estopped }
+    pub fn emergency_stop(&mut self) {
+        self.set_paused(true);
+        self.estopped = true;
+        self.estop_pending = true;
+        self.log(Log { text: local_str!("emergency stop, outputs will be forced off"), color: 14 })
+    }
+
+    pub fn rearm(&mut self) {
+        if self.estopped {
+            self.estopped = false;
+            self.log(Log { text: local_str!("re-armed"), color: 13 });
+            self.set_paused(false)
+        }
+    }
+
+    pub fn request_snapshot(&mut self, path: String) {
+        self.config.tui.log(format!("snapshot to {path} will be written after the next storage update"), 8);
+        self.snapshot_path = Some(path)
+    }
+
+    pub fn set_reload(&mut self, config_path: String, slot: &FactorySlot) {
+        self.reload = Some((config_path, Arc::downgrade(slot)))
+    }
+
+    pub fn reload(&self) {
+        let Some((path, slot)) = &self.reload else {
+            return self.log(Log { text: local_str!("reload: not loaded from a config file"), color: 14 });
+        };
+        let Some(slot) = slot.upgrade() else { return };
+        match reload_factory(self.config.tui.clone(), path, &slot, Some(self)) {
+            Ok(()) => self.log(Log { text: local_fmt!("reloaded {path}"), color: 13 }),
+            Err(e) => self.log(Log { text: local_fmt!("reload: {e}"), color: 14 }),
+        }
+    }
+
+    fn write_snapshot(&self, path: &str) -> Result<(), LocalStr> {
+        let now = Instant::now();
+        let storages: Vec<_> = (self.storages.iter())
+            .map(|storage| {
+                let storage = storage.borrow();
+                let stacks: Vec<_> = storage.stacks().iter().flatten().collect();
+                json!({
+                    "name": &**storage.name(),
+                    "used_slots": stacks.len(),
+                    "total_slots": storage.stacks().len(),
+                    "items": stacks,
+                })
+            })
+            .collect();
+        let processes: Vec<_> = (self.processes.iter())
+            .map(|entry| {
+                json!({
+                    "name": entry.options.name.as_deref(),
+                    "enabled": !entry.options.disabled,
+                    "background": entry.options.background,
+                    "secs_since_run": entry.last_run.get().map(|x| (now - x).as_secs_f64()),
+                })
+            })
+            .collect();
+        let fluids: Vec<_> = (self.fluid_storages.iter())
+            .map(|storage| {
+                let storage = storage.borrow();
+                json!({ "fluid": &*storage.config.fluid, "stored": storage.n_stored_lo, "capacity": storage.config.capacity })
+            })
+            .collect();
+        let clients: Vec<_> = self.config.server.borrow().clients().map(|x| x.to_std_string()).collect();
+        let snapshot = json!({
+            "cycle": self.stats.n_cycles,
+            "paused": self.paused,
+            "storages": storages,
+            "processes": processes,
+            "fluids": fluids,
+            "clients": clients,
+        });
+        write_json_atomic(path, &snapshot)
+    }
+
+    pub fn add_to_recipe_book(&mut self, entry: RecipeBookEntry) { self.recipe_book.push(entry) }
+    pub fn get_recipe_book(&self) -> &[RecipeBookEntry] { &self.recipe_book }
     pub fn get_n_stored(&self, item: &Rc<Item>) -> i32 { self.items.get(item).map_or(0, |info| info.borrow().n_stored) }
     pub fn add_fluid_storage(&mut self, config: FluidStorageConfig) {
         self.fluid_storages.push(Rc::new_cyclic(|weak| {
@@ -228,8 +594,38 @@ impl Factory {
         }))
     }
 
+    pub fn describe_status(&self) -> LocalStr {
+        let uptime = self.started.elapsed().as_secs();
+        let recent = &self.stats.recent_cycle_times;
+        let avg_cycle_time = recent.iter().sum::<Duration>().as_secs_f64() / recent.len().max(1) as f64;
+        local_fmt!(
+            "up {}h{:02}m{:02}s, {} cycles, avg cycle {:.3}s over last {}, {} clients, {} items moved",
+            uptime / 3600,
+            uptime / 60 % 60,
+            uptime % 60,
+            self.stats.n_cycles,
+            avg_cycle_time,
+            recent.len(),
+            self.config.server.borrow().n_clients(),
+            self.stats.n_items_moved.get()
+        )
+    }
+
+    pub fn get_cycle_status(&self) -> CycleStatus {
+        CycleStatus {
+            last_cycle_time: self.last_cycle_time,
+            min_cycle_time: self.config.min_cycle_time,
+            cycle_interval: self.cycle_interval,
+            n_clients: self.config.server.borrow().n_clients(),
+            paused: self.paused,
+        }
+    }
+
     pub fn log(&self, action: Log) {
         self.config.tui.log(action.text.to_std_string(), action.color);
+        if let (Some(webhook), 1 | 6 | 14) = (&self.config.webhook, action.color) {
+            webhook.push(&action.text)
+        }
         let server = self.config.server.borrow();
         for client in &self.config.log_clients {
             server.enqueue_request_group(client, vec![ActionFuture::from(action.clone()).into()]);
@@ -237,6 +633,7 @@ impl Factory {
     }
 
     pub fn register_stored_item(&mut self, item: Rc<Item>, detail: &Rc<Detail>) -> &mut ItemInfo {
+        let item = relabel(&self.config.relabel, &item);
         match self.items.entry(item) {
             Entry::Occupied(x) => x.into_mut().get_mut(),
             Entry::Vacant(x) => {
@@ -247,6 +644,7 @@ impl Factory {
                     detail: detail.clone(),
                     n_stored: 0,
                     n_backup: 0,
+                    n_locked: 0,
                     providers: BinaryHeap::new(),
                 }))
                 .get_mut()
@@ -288,9 +686,9 @@ impl Factory {
                     }
                 }
             }
-            Filter::Custom { func, .. } => {
+            Filter::LabelFuzzy(_) | Filter::Custom { .. } => {
                 for (item, info) in &self.items {
-                    if func(item, &info.borrow().detail) {
+                    if filter.apply(item, &info.borrow().detail) {
                         on_candidate((item, info))
                     }
                 }
@@ -299,10 +697,162 @@ impl Factory {
         best
     }
 
+    pub fn get_item_snapshot(&self, alphabetical: bool) -> Vec<(LocalStr, i32)> {
+        let mut result = Vec::from_iter(self.items.values().map(|info| {
+            let info = info.borrow();
+            (info.detail.label.clone(), info.n_stored)
+        }));
+        if alphabetical {
+            result.sort_by(|x, y| x.0.cmp(&y.0))
+        } else {
+            result.sort_by(|x, y| y.1.cmp(&x.1).then_with(|| x.0.cmp(&y.0)))
+        }
+        result
+    }
+
     pub fn search_n_stored(&self, filter: &Filter) -> i32 {
         self.search_item(filter).map_or(0, |(_, info)| info.borrow().n_stored)
     }
 
+    pub fn request_craft(&mut self, item: Filter, n: i32) {
+        if let Some(request) = self.craft_requests.iter_mut().find(|x| x.item == item) {
+            request.n_target += n
+        } else {
+            let n_target = self.search_n_stored(&item) + n;
+            self.craft_requests.push(CraftRequest { item, n_target })
+        }
+        self.save_craft_requests()
+    }
+
+    fn save_craft_requests(&self) {
+        let Some(path) = &self.config.craft_queue_file else { return };
+        let requests: Vec<_> = (self.craft_requests.iter())
+            .map(|request| SavedCraftRequest {
+                item: request.item.describe().to_std_string(),
+                n_target: request.n_target,
+            })
+            .collect();
+        if let Err(e) = write_json_atomic(path, &requests) {
+            self.log(Log { text: local_fmt!("failed to save craft queue: {}", e), color: 14 })
+        }
+    }
+
+    // Requests are matched back to recipe outputs by description, so ones whose recipe was removed are dropped.
+    fn load_craft_requests(&mut self) {
+        let Some(path) = self.config.craft_queue_file.clone() else { return };
+        let Ok(file) = File::open(&*path) else { return };
+        let requests: Vec<SavedCraftRequest> = match serde_json::from_reader(BufReader::new(file)) {
+            Ok(requests) => requests,
+            Err(e) => return self.log(Log { text: local_fmt!("{}: {}", path, e), color: 14 }),
+        };
+        for request in requests {
+            let item = (self.recipe_book.iter().flat_map(|entry| &entry.outputs))
+                .find(|output| *output.describe() == *request.item)
+                .cloned();
+            let Some(item) = item else {
+                self.log(Log { text: local_fmt!("craft dropped: {} has no recipe", request.item), color: 1 });
+                continue;
+            };
+            self.log(Log { text: local_fmt!("craft resumed: {}*{}", request.item, request.n_target), color: 13 });
+            self.craft_requests.push(CraftRequest { item, n_target: request.n_target })
+        }
+    }
+
+    pub fn get_craft_target(&self, item: &Filter) -> i32 {
+        self.craft_requests.iter().find(|x| x.item == *item).map_or(0, |x| x.n_target)
+    }
+
+    fn check_alerts(&mut self) {
+        let n_stored: Vec<_> = self.config.alerts.iter().map(|rule| self.search_n_stored(&rule.item)).collect();
+        let mut logs = Vec::new();
+        for ((rule, level), n_stored) in self.config.alerts.iter().zip(&mut self.alert_levels).zip(n_stored) {
+            match update_alert_level(level, n_stored, rule.n_min, rule.once) {
+                Some(true) => {
+                    let text = local_fmt!("stock recovered: {}*{}", rule.item.describe(), n_stored);
+                    logs.push(Log { text, color: 13 })
+                }
+                Some(false) => {
+                    let text = local_fmt!("low stock: {}*{} (min {})", rule.item.describe(), n_stored, rule.n_min);
+                    logs.push(Log { text, color: 1 })
+                }
+                None => (),
+            }
+        }
+        let rules = &self.config.fluid_alerts;
+        let n_stored: Vec<_> = rules.iter().map(|rule| self.search_n_fluid(&rule.fluid)).collect();
+        for ((rule, level), n_stored) in rules.iter().zip(&mut self.fluid_alert_levels).zip(n_stored) {
+            match update_alert_level(level, n_stored, rule.n_min, rule.once) {
+                Some(true) => {
+                    let text = local_fmt!("fluid recovered: {}*{}", rule.fluid, n_stored);
+                    logs.push(Log { text, color: 13 })
+                }
+                Some(false) => {
+                    let text = local_fmt!("low fluid: {}*{} (min {})", rule.fluid, n_stored, rule.n_min);
+                    logs.push(Log { text, color: 1 })
+                }
+                None => (),
+            }
+        }
+        for log in logs {
+            self.log(log)
+        }
+    }
+
+    fn check_stalls(&mut self) {
+        let reserved_by = take(self.reserved_by.get_mut());
+        let Some(threshold) = self.config.stall_cycles.filter(|&x| x > 0) else { return };
+        let mut texts = Vec::new();
+        for entry in &self.processes {
+            let (Some(pending), Some(name)) = (entry.pending.take(), &entry.options.name) else { continue };
+            if !pending || reserved_by.contains(name) {
+                entry.n_stalled.set(0);
+                continue;
+            }
+            let n_stalled = entry.n_stalled.get() + 1;
+            entry.n_stalled.set(n_stalled);
+            if n_stalled % threshold == 0 {
+                texts.push(local_fmt!("{}: craftable demand but no progress for {} cycles", name, n_stalled))
+            }
+        }
+        for text in texts {
+            self.log(Log { text, color: 1 })
+        }
+    }
+
+    fn check_stuck_bus(&mut self) {
+        let Some(threshold) = self.config.bus_stuck_cycles.filter(|&x| x > 0) else { return };
+        let contents = &self.bus_contents;
+        self.bus_stuck.retain(|slot, _| contents.contains_key(slot));
+        let mut texts = Vec::new();
+        for (slot, (label, size)) in &self.bus_contents {
+            let n_cycles = self.bus_stuck.entry(*slot).or_default();
+            *n_cycles += 1;
+            if *n_cycles % threshold == 0 {
+                texts.push(local_fmt!("bus slot {} stuck with {}*{} for {} cycles", slot + 1, label, size, n_cycles))
+            }
+        }
+        for text in texts {
+            self.log(Log { text, color: 14 })
+        }
+    }
+
+    fn retire_craft_requests(&mut self) {
+        let mut requests = take(&mut self.craft_requests);
+        let n_requests = requests.len();
+        requests.retain(|request| {
+            let n_stored = self.search_n_stored(&request.item);
+            if n_stored < request.n_target {
+                return true;
+            }
+            self.log(Log { text: local_fmt!("craft done: {}*{}", request.item.describe(), n_stored), color: 13 });
+            false
+        });
+        self.craft_requests = requests;
+        if self.craft_requests.len() != n_requests {
+            self.save_craft_requests()
+        }
+    }
+
     pub fn bus_allocate(&mut self) -> LocalReceiver<usize> {
         let (sender, receiver) = make_local_one_shot();
         self.bus_wait_queue.push_back(sender);
@@ -335,33 +885,126 @@ impl Factory {
         }
     }
 
+    fn check_transfer_size(&self, label: &str, size: i32) {
+        if self.config.warn_transfer_size.is_some_and(|limit| size > limit) {
+            self.log(Log { text: local_fmt!("unexpectedly large transfer: {label}*{size}"), color: 1 })
+        }
+    }
+
     fn deposit_item(&self, bus_slot: usize, mut stack: DetailStack, tasks: &mut Vec<ChildTask<Result<(), LocalStr>>>) {
         self.log(Log { text: local_fmt!("{}*{}", stack.detail.label, stack.size), color: 1 });
+        self.check_transfer_size(&stack.detail.label, stack.size);
+        self.stats.add_moved(stack.size);
+        if let Some(addr) = &self.config.trash_addr {
+            if self.config.trash.iter().any(|filter| filter.apply(&stack.item, &stack.detail)) {
+                return tasks.push(self.trash_item(addr, bus_slot, stack));
+            }
+        }
         while stack.size > 0 {
-            let mut best: Option<(&Rc<RefCell<dyn Storage>>, i32)> = None;
-            for storage in &self.storages {
-                let Some(prio) = storage.borrow_mut().deposit_priority(&stack.item, &stack.detail) else { continue };
+            let mut best: Option<(usize, (i32, f64, i32))> = None;
+            for (i, storage) in self.storages.iter().enumerate() {
+                let mut storage_mut = storage.borrow_mut();
+                let Some(prio) = storage_mut.deposit_priority(&stack.item, &stack.detail) else { continue };
+                // Among equal insertion priorities, storages take turns by deposits relative to weight, 1 if unset.
+                let load = -(self.n_deposited[i].get() as f64 / storage_mut.weight().unwrap_or(1.));
+                let prio = (storage_mut.insertion_priority(), load, prio);
                 if best.as_ref().map_or(true, |&(_, best)| prio > best) {
-                    best = Some((storage, prio))
+                    best = Some((i, prio))
                 }
             }
-            if let Some((storage, _)) = best {
-                let DepositResult { n_deposited, task } = storage.borrow_mut().deposit(&stack, bus_slot);
+            if let Some((i, _)) = best {
+                let DepositResult { n_deposited, task } = self.storages[i].borrow_mut().deposit(&stack, bus_slot);
+                self.n_deposited[i].set(self.n_deposited[i].get() + n_deposited as i64);
                 stack.size -= n_deposited;
                 tasks.push(task)
-            } else {
+            } else if self.storages.iter().any(|storage| storage.borrow().accepts(&stack.item, &stack.detail)) {
                 tasks.push(spawn(async { Err(local_str!("storage is full")) }));
                 break;
+            } else {
+                if self.bus_rejected.borrow_mut().insert(stack.item.clone()) {
+                    let text = local_fmt!("{}*{}: rejected by every storage", stack.detail.label, stack.size);
+                    self.log(Log { text, color: 1 });
+                }
+                if let Some(addr) = &self.config.trash_addr {
+                    return tasks.push(self.trash_item(addr, bus_slot, stack));
+                }
+                // Without a trash_addr it stays on the bus, warned about once, until the config changes to take it.
+                break;
             }
         }
     }
 
+    fn trash_item(&self, addr: &LocalStr, bus_slot: usize, stack: DetailStack) -> ChildTask<Result<(), LocalStr>> {
+        let server = self.config.server.borrow();
+        let access = server.load_balance(&self.config.bus_accesses);
+        let action = ActionFuture::from(Call {
+            addr: access.addr.clone(),
+            args: vec!["pushItems".into(), addr.clone().into(), (bus_slot + 1).into(), stack.size.into()],
+        });
+        server.enqueue_request_group(&access.client, vec![action.clone().into()]);
+        let weak = self.weak.clone();
+        spawn(async move {
+            let TransferResult { n_moved } = action.await?.try_into()?;
+            alive!(weak, this);
+            *this.n_trashed.borrow_mut().entry(stack.detail.label.clone()).or_default() += n_moved;
+            Ok(())
+        })
+    }
+
+    fn send_cycle_stats(&self, cycle_time: Duration) {
+        if let Some(statsd) = &self.config.statsd {
+            statsd.timing("cycle_time", cycle_time);
+            statsd.count("cycles", 1);
+            statsd.gauge("bus_updates", self.n_bus_updates as _);
+            statsd.gauge("fluid_bus_updates", self.n_fluid_bus_updates as _);
+            statsd.gauge("clients", self.config.server.borrow().n_clients() as _)
+        }
+    }
+
+    fn log_trashed(&mut self) {
+        let trashed = take(self.n_trashed.get_mut());
+        if !trashed.is_empty() {
+            let items: Vec<_> = trashed.iter().map(|(label, n)| format!("{label}*{n}")).collect();
+            self.log(Log { text: local_fmt!("trashed {}", items.join(", ")), color: 8 })
+        }
+    }
+
     pub fn reserve_item(&self, reason: &str, item: &Rc<Item>, size: i32) -> Reservation {
         let mut info = self.items.get(item).unwrap().borrow_mut();
+        self.n_reservations.set(self.n_reservations.get() + 1);
+        self.reserved_by.borrow_mut().insert(reason.into());
         self.log(Log { text: local_fmt!("{reason}: {}*{size}", info.detail.label,), color: 3 });
+        self.check_transfer_size(&info.detail.label, size);
+        self.stats.add_moved(size);
         info.reserve(size)
     }
 
+    // Used and total slots of each storage, as of the last storage update.
+    pub fn get_storage_occupancy(&self) -> Vec<(LocalStr, usize, usize)> {
+        (self.storages.iter())
+            .map(|storage| {
+                let storage = storage.borrow();
+                let stacks = storage.stacks();
+                (storage.name().clone(), stacks.iter().flatten().count(), stacks.len())
+            })
+            .collect()
+    }
+
+    pub fn get_fluid_snapshot(&self) -> Vec<(LocalStr, i64, i64)> {
+        let mut result: Vec<(LocalStr, i64, i64)> = Vec::new();
+        for storage in &self.fluid_storages {
+            let storage = storage.borrow();
+            if let Some(entry) = result.iter_mut().find(|x| x.0 == storage.config.fluid) {
+                entry.1 += storage.n_stored_lo;
+                entry.2 += storage.config.capacity
+            } else {
+                result.push((storage.config.fluid.clone(), storage.n_stored_lo, storage.config.capacity))
+            }
+        }
+        result.sort_by(|x, y| x.0.cmp(&y.0));
+        result
+    }
+
     pub fn search_n_fluid(&self, fluid: &str) -> i64 {
         let mut sum = 0;
         for storage in &self.fluid_storages {
@@ -374,16 +1017,16 @@ impl Factory {
     }
 
     pub fn get_fluid_availability(&self, fluid: &str, allow_backup: bool, extra_backup: i64) -> i64 {
-        let mut n_available = self.search_n_fluid(fluid) - extra_backup;
-        if !allow_backup {
-            n_available -= self.fluid_backups.get(fluid).copied().unwrap_or_default()
-        }
-        n_available.max(0)
+        let n_backup = self.fluid_backups.get(fluid).copied().unwrap_or_default();
+        fluid_availability(self.search_n_fluid(fluid), n_backup, allow_backup, extra_backup)
     }
 
-    pub fn fluid_bus_allocate(&mut self) -> LocalReceiver<usize> {
+    pub fn get_fluid_bus_capacity(&self, bus: usize) -> i64 { self.config.fluid_bus_capacities[bus] }
+
+    // Resolves to a bus holding at least n_needed; a transfer that only needs some bus asks for 0.
+    pub fn fluid_bus_allocate(&mut self, n_needed: i64) -> LocalReceiver<usize> {
         let (sender, receiver) = make_local_one_shot();
-        self.fluid_bus_wait_queue.push_back(sender);
+        self.fluid_bus_wait_queue.push_back((n_needed, sender));
         if self.fluid_bus_task.is_none() {
             self.fluid_bus_task = Some(spawn(fluid_bus_main(self.weak.clone())))
         }
@@ -391,13 +1034,32 @@ impl Factory {
     }
 
     pub fn fluid_bus_free(&mut self, bus: usize) {
-        if let Some(state) = self.fluid_bus_wait_queue.pop_front() {
-            state.send(Ok(bus))
-        } else {
+        let mut free = vec![bus];
+        self.grant_fluid_buses(&mut free);
+        if !free.is_empty() {
             self.fluid_bus_allocations.remove(&bus);
         }
     }
 
+    // Waiters are served in order, each with the smallest free bus it fits on so bigger buses stay open for bigger
+    // transfers. A waiter no free bus is big enough for is skipped until one frees up.
+    fn grant_fluid_buses(&mut self, free: &mut Vec<usize>) {
+        let mut i = 0;
+        while !free.is_empty() && i < self.fluid_bus_wait_queue.len() {
+            let n_needed = self.fluid_bus_wait_queue[i].0;
+            let fits = (free.iter().enumerate())
+                .filter(|&(_, &bus)| self.get_fluid_bus_capacity(bus) >= n_needed)
+                .min_by_key(|&(_, &bus)| self.get_fluid_bus_capacity(bus));
+            let Some((pos, _)) = fits else {
+                i += 1;
+                continue;
+            };
+            let bus = free.remove(pos);
+            self.fluid_bus_allocations.insert(bus);
+            self.fluid_bus_wait_queue.remove(i).unwrap().1.send(Ok(bus))
+        }
+    }
+
     pub fn fluid_bus_deposit(&mut self, buses: impl IntoIterator<Item = usize>) {
         if self.fluid_bus_task.is_none() {
             let mut ever_freed = false;
@@ -459,6 +1121,8 @@ impl Factory {
 
     pub fn reserve_fluid(&self, reason: &str, fluid: &str, mut qty: i64) -> FluidReservation {
         self.log(Log { text: local_fmt!("{reason}: {fluid}*{qty}",), color: 3 });
+        self.n_reservations.set(self.n_reservations.get() + 1);
+        self.reserved_by.borrow_mut().insert(reason.into());
         let mut extractors = Vec::new();
         while qty > 0 {
             let mut best = None;
@@ -481,6 +1145,16 @@ impl Factory {
         FluidReservation { extractors }
     }
 
+    fn adapt_cycle_interval(&mut self, busy: bool) {
+        let Some(max_cycle_time) = self.config.max_cycle_time else { return };
+        let min_cycle_time = self.config.min_cycle_time;
+        self.cycle_interval = if busy {
+            min_cycle_time
+        } else {
+            (self.cycle_interval + self.cycle_interval / 2).min(max_cycle_time).max(min_cycle_time)
+        }
+    }
+
     fn end_of_cycle(&mut self) {
         for storage in &self.storages {
             storage.borrow_mut().cleanup()
@@ -489,23 +1163,68 @@ impl Factory {
             storage.borrow_mut().n_stored_hi = 0;
             storage.borrow_mut().n_stored_lo = 0
         }
-        self.items.clear();
-        self.label_map.clear();
-        self.name_map.clear();
+    }
+}
+
+// Returns Some(true) when the level recovers and Some(false) when a new shortage should be reported.
+fn update_alert_level<T: Copy + Ord>(level: &mut Option<T>, n: T, n_min: T, once: bool) -> Option<bool> {
+    if n >= n_min {
+        level.take().map(|_| true)
+    } else if level.map_or(true, |last| !once && n < last) {
+        *level = Some(n);
+        Some(false)
+    } else {
+        None
+    }
+}
+
+async fn check_connected(factory: Weak<RefCell<Factory>>) {
+    sleep(CONNECT_GRACE).await;
+    let Some(this) = factory.upgrade() else { return };
+    let this = this.borrow();
+    let server = this.config.server.borrow();
+    let missing: Vec<_> = this.config.clients.iter().filter(|x| !server.is_connected(x)).cloned().collect();
+    drop(server);
+    for client in missing {
+        this.log(Log { text: local_fmt!("{client} hasn't connected since startup"), color: 1 })
     }
 }
 
 async fn factory_main(factory: Weak<RefCell<Factory>>) -> Result<(), LocalStr> {
     let mut cycle_start_last: Option<Instant> = None;
-    let mut n_cycles: usize = 0;
+    let mut status_last = Instant::now();
     loop {
         let cycle_start_time = Instant::now();
-        {
+        let estop_tasks = {
+            alive_mut!(factory, this);
+            if take(&mut this.estop_pending) {
+                let this = &*this;
+                this.processes.iter().filter_map(|entry| entry.process.borrow().on_estop(this)).collect()
+            } else {
+                Vec::new()
+            }
+        };
+        if let Err(e) = join_tasks(estop_tasks).await {
+            alive!(factory, this);
+            this.log(Log { text: local_fmt!("emergency stop failed: {}", e), color: 14 })
+        }
+        // Without processes that ignore pausing there is nothing to do, so leave storages and buses alone.
+        let paused_cycle_time = {
+            alive!(factory, this);
+            let idle = this.paused && !this.processes.iter().any(|entry| entry.options.ignore_pause);
+            idle.then_some(this.config.min_cycle_time)
+        };
+        if let Some(min_cycle_time) = paused_cycle_time {
+            sleep_until(cycle_start_time + min_cycle_time).await;
+            cycle_start_last = Some(cycle_start_time);
+            continue;
+        }
+        let n_items_moved = {
             alive_mut!(factory, this);
             let text = if let Some(last) = cycle_start_last {
                 local_fmt!(
                     "OCRemote #{}, nBusUpdates={},{}, cycleTime={:.3}",
-                    n_cycles,
+                    this.stats.n_cycles,
                     this.n_bus_updates,
                     this.n_fluid_bus_updates,
                     (cycle_start_time - last).as_secs_f64()
@@ -515,8 +1234,12 @@ async fn factory_main(factory: Weak<RefCell<Factory>>) -> Result<(), LocalStr> {
             };
             this.log(Log { text, color: 0 });
             this.n_bus_updates = 0;
-            this.n_fluid_bus_updates = 0
-        }
+            this.n_fluid_bus_updates = 0;
+            this.items.clear();
+            this.label_map.clear();
+            this.name_map.clear();
+            this.stats.n_items_moved.get()
+        };
         let result = async {
             update_storages(&factory).await?;
             run_processes(&factory).await
@@ -531,7 +1254,7 @@ async fn factory_main(factory: Weak<RefCell<Factory>>) -> Result<(), LocalStr> {
             if let Err(e) = result {
                 this.log(Log { text: local_fmt!("cycle failed: {}", e), color: 14 })
             } else {
-                n_cycles += 1;
+                this.stats.n_cycles += 1;
                 if bus_task.is_none() && this.n_bus_updates == 0 {
                     bus_task = Some(spawn(bus_main(factory.clone())))
                 }
@@ -546,7 +1269,24 @@ async fn factory_main(factory: Weak<RefCell<Factory>>) -> Result<(), LocalStr> {
         let min_cycle_time = {
             alive_mut!(factory, this);
             this.end_of_cycle();
-            this.config.min_cycle_time
+            this.check_stuck_bus();
+            this.log_trashed();
+            this.stats.n_bus_updates += this.n_bus_updates;
+            this.stats.n_fluid_bus_updates += this.n_fluid_bus_updates;
+            let cycle_time = Instant::now() - cycle_start_time;
+            this.last_cycle_time = Some(cycle_time);
+            this.stats.recent_cycle_times.push_back(cycle_time);
+            if this.stats.recent_cycle_times.len() > N_RECENT_CYCLES {
+                this.stats.recent_cycle_times.pop_front();
+            }
+            if this.config.status_interval.is_some_and(|interval| status_last.elapsed() >= interval) {
+                status_last = Instant::now();
+                this.log(Log { text: this.describe_status(), color: 0 })
+            }
+            this.send_cycle_stats(cycle_time);
+            let busy = this.stats.n_items_moved.get() != n_items_moved || !this.craft_requests.is_empty();
+            this.adapt_cycle_interval(busy);
+            jitter(this.cycle_interval, this.config.cycle_jitter)
         };
         sleep_until(cycle_start_time + min_cycle_time).await;
         cycle_start_last = Some(cycle_start_time)
@@ -561,26 +1301,88 @@ async fn update_storages(factory: &Weak<RefCell<Factory>>) -> Result<(), LocalSt
         tasks.extend(factory.fluid_storages.iter().map(|storage| storage.borrow().update()))
     };
     join_tasks(tasks).await?;
-    alive!(factory, this);
+    alive_mut!(factory, this);
     let mut n_total = 0;
     for (_, item) in &this.items {
         n_total += item.borrow().n_stored
     }
     this.log(Log { text: local_fmt!("storage: {} items, {} types", n_total, this.items.len()), color: 13 });
+    if let Some(statsd) = &this.config.statsd {
+        statsd.gauge("items", n_total as _);
+        statsd.gauge("item_types", this.items.len() as _);
+        let occupancy = this.get_storage_occupancy();
+        statsd.gauge("storage_used_slots", occupancy.iter().map(|x| x.1).sum::<usize>() as _);
+        statsd.gauge("storage_total_slots", occupancy.iter().map(|x| x.2).sum::<usize>() as _);
+        let fluids = this.get_fluid_snapshot();
+        statsd.gauge("fluid_used", fluids.iter().map(|x| x.1).sum());
+        statsd.gauge("fluid_capacity", fluids.iter().map(|x| x.2).sum())
+    }
     for (filter, n_backup) in &this.config.backups {
         if let Some((_, info)) = this.search_item(filter) {
             info.borrow_mut().n_backup += n_backup
         }
     }
+    this.retire_craft_requests();
+    this.check_alerts();
+    if let Some(path) = this.snapshot_path.take() {
+        let log = match this.write_snapshot(&path) {
+            Ok(()) => Log { text: local_fmt!("snapshot written to {path}"), color: 13 },
+            Err(e) => Log { text: local_fmt!("snapshot failed: {e}"), color: 14 },
+        };
+        this.log(log)
+    }
     Ok(())
 }
 
 async fn run_processes(factory: &Weak<RefCell<Factory>>) -> Result<(), LocalStr> {
     let tasks = {
-        alive!(factory, this);
-        this.processes.iter().map(|process| process.borrow().run(this)).collect()
+        alive_mut!(factory, this);
+        let n_reservations = this.n_reservations.get();
+        let now = Instant::now();
+        // Rotating who starts lets every process take the first free bus slot in turn.
+        let offset = if this.config.fair_scheduling && !this.processes.is_empty() {
+            this.rotation = (this.rotation + 1) % this.processes.len();
+            this.rotation
+        } else {
+            0
+        };
+        let run = |this: &Factory, background: bool| {
+            let mut busy = false;
+            let (head, tail) = this.processes.split_at(offset);
+            let tasks = (tail.iter().chain(head))
+                .filter(|entry| !entry.options.disabled && entry.options.background == background)
+                .filter(|entry| !this.paused || entry.options.ignore_pause)
+                .filter(|entry| match (entry.options.interval, entry.last_run.get()) {
+                    (Some(interval), Some(last_run)) => now - last_run >= interval,
+                    _ => true,
+                })
+                .map(|entry| {
+                    entry.last_run.set(Some(now));
+                    let process = entry.process.borrow();
+                    let pending = process.has_pending_work(this);
+                    entry.pending.set(Some(pending));
+                    busy |= pending;
+                    process.run(this)
+                })
+                .collect::<Vec<_>>();
+            (tasks, busy)
+        };
+        let (mut tasks, busy) = run(this, false);
+        // Background processes only get cycles where nothing else reserved any input. Most processes reserve from
+        // their spawned tasks, after this point, so their pending work counts too.
+        if !busy && this.n_reservations.get() == n_reservations {
+            this.background_credit = (this.background_credit + this.config.background_budget).min(1.);
+            if this.background_credit >= 1. {
+                this.background_credit -= 1.;
+                tasks.extend(run(this, true))
+            }
+        }
+        tasks
     };
-    join_tasks(tasks).await
+    let result = join_tasks(tasks).await;
+    alive_mut!(factory, this);
+    this.check_stalls();
+    result
 }
 
 async fn bus_main(factory: Weak<RefCell<Factory>>) -> Result<(), LocalStr> {
@@ -614,9 +1416,13 @@ async fn bus_update(factory: &Weak<RefCell<Factory>>) -> Result<bool, LocalStr>
     {
         alive_mut!(factory, this);
         let mut free_slots = Vec::new();
+        this.bus_contents.clear();
+        let on_bus: FnvHashSet<_> = stacks.iter().flatten().map(|x| x.item.clone()).collect();
+        this.bus_rejected.get_mut().retain(|item| on_bus.contains(item));
         for (slot, stack) in stacks.into_iter().enumerate() {
             if !this.bus_allocations.contains(&slot) {
                 if let Some(stack) = stack {
+                    this.bus_contents.insert(slot, (stack.detail.label.clone(), stack.size));
                     this.deposit_item(slot, stack, &mut tasks);
                 } else {
                     free_slots.push(slot)
@@ -647,7 +1453,7 @@ async fn fluid_bus_main(factory: Weak<RefCell<Factory>>) -> Result<(), LocalStr>
         match result {
             Err(e) => {
                 let text = local_fmt!("fluid bus failed: {}", e);
-                for sender in take(&mut this.fluid_bus_wait_queue) {
+                for (_, sender) in take(&mut this.fluid_bus_wait_queue) {
                     sender.send(Err(text.clone()))
                 }
                 this.log(Log { text, color: 14 });
@@ -689,19 +1495,11 @@ async fn fluid_bus_update(factory: &Weak<RefCell<Factory>>) -> Result<bool, Loca
                 }
             }
         }
-        while !empty_buses.is_empty() && !this.fluid_bus_wait_queue.is_empty() {
-            let bus = empty_buses.pop().unwrap();
-            this.fluid_bus_allocations.insert(bus);
-            this.fluid_bus_wait_queue.pop_front().unwrap().send(Ok(bus))
-        }
+        this.grant_fluid_buses(&mut empty_buses)
     }
     join_tasks(tasks).await?;
     alive_mut!(factory, this);
-    while !empty_buses_after_deposit.is_empty() && !this.fluid_bus_wait_queue.is_empty() {
-        let bus = empty_buses_after_deposit.pop().unwrap();
-        this.fluid_bus_allocations.insert(bus);
-        this.fluid_bus_wait_queue.pop_front().unwrap().send(Ok(bus))
-    }
+    this.grant_fluid_buses(&mut empty_buses_after_deposit);
     let mut ever_freed = false;
     for slot in take(&mut this.fluid_bus_free_queue) {
         this.fluid_bus_allocations.remove(&slot);
@@ -710,6 +1508,25 @@ async fn fluid_bus_update(factory: &Weak<RefCell<Factory>>) -> Result<bool, Loca
     Ok(ever_freed)
 }
 
+// Moves `n` of a fluid through the fluid buses, each transfer as much as the bus it got holds. `transfer` moves the
+// amount given through the bus given, which is freed again once it's done.
+pub async fn transfer_via_fluid_buses<T: Future<Output = Result<(), LocalStr>>>(
+    factory: &Weak<RefCell<Factory>>,
+    mut n: i64,
+    mut transfer: impl FnMut(usize, i64) -> T,
+) -> Result<(), LocalStr> {
+    while n > 0 {
+        let bus = alive(factory)?.borrow_mut().fluid_bus_allocate(0);
+        let bus = bus.await?;
+        let qty = n.min(alive(factory)?.borrow().get_fluid_bus_capacity(bus));
+        n -= qty;
+        let result = transfer(bus, qty).await;
+        alive(factory)?.borrow_mut().fluid_bus_deposit([bus]);
+        result?
+    }
+    Ok(())
+}
+
 pub fn read_tanks<'a, T: GetClient + 'a>(
     server: &Server,
     accesses: impl IntoIterator<Item = &'a T>,
@@ -766,3 +1583,425 @@ impl FluidStorage {
         })
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::process::Process;
+    use std::net::UdpSocket;
+    use tokio::{
+        task::{yield_now, LocalSet},
+        time::advance,
+    };
+
+    // Tests run on a paused clock, so time only passes when a test advances it.
+    pub fn run_local<T>(future: impl Future<Output = T>) -> T {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().start_paused(true).build().unwrap();
+        LocalSet::new().block_on(&runtime, future)
+    }
+
+    pub fn test_config(tui: &Rc<Tui>) -> FactoryConfig {
+        FactoryConfig {
+            tui: tui.clone(),
+            // Nothing to load from an empty path, and saving to it fails, so tests stay off the disk.
+            detail_cache: DetailCache::new(tui, "".into()),
+            server: Server::new(tui.clone()),
+            min_cycle_time: Duration::from_secs(1),
+            max_cycle_time: None,
+            cycle_jitter: Duration::ZERO,
+            log_clients: Vec::new(),
+            bus_accesses: Vec::new(),
+            fluid_bus_accesses: Vec::new(),
+            fluid_bus_capacities: Vec::new(),
+            backups: Vec::new(),
+            fluid_backups: Vec::new(),
+            warn_transfer_size: None,
+            bus_stuck_cycles: None,
+            stall_cycles: None,
+            status_interval: None,
+            background_budget: 0.,
+            statsd: None,
+            webhook: None,
+            relabel: Vec::new(),
+            trash: Vec::new(),
+            trash_addr: None,
+            api: None,
+            fair_scheduling: false,
+            craft_queue_file: None,
+            alerts: Vec::new(),
+            fluid_alerts: Vec::new(),
+            clients: Vec::new(),
+            turtle_clients: Vec::new(),
+        }
+    }
+
+    // Builds a factory whose cycle loop never starts, so tests can step it by hand.
+    pub fn test_factory(config: FactoryConfig, builder: impl FnOnce(&mut Factory)) -> Rc<RefCell<Factory>> {
+        let factory = config
+            .build(|factory| {
+                builder(factory);
+                Ok(())
+            })
+            .unwrap();
+        let mut this = factory.borrow_mut();
+        this._task = spawn(async { Ok(()) });
+        this._connect_check = spawn(async {});
+        drop(this);
+        factory
+    }
+
+    // Runs `test` on a paused clock against a test_factory built from test_config; tests needing other settings
+    // adjust its config before using it.
+    pub fn with_test_factory<F: Future>(test: impl FnOnce(Rc<RefCell<Factory>>, Rc<Tui>) -> F) -> F::Output {
+        run_local(async {
+            let tui = Rc::<Tui>::default();
+            let factory = test_factory(test_config(&tui), |_| ());
+            test(factory, tui).await
+        })
+    }
+
+    pub fn logs(tui: &Tui) -> Vec<String> {
+        tui.logs.borrow().iter().map(|line| line.spans[1].content.to_string()).collect()
+    }
+
+    // Registers `n` of an item as stored and free to reserve, labeled by its name.
+    pub fn stock(factory: &mut Factory, name: &str, n: i32) -> Rc<Item> {
+        let item = Rc::new(Item {
+            name: name.to_owned().into(),
+            nbt_hash: None,
+            #[cfg(feature = "plethora")]
+            damage: 0,
+        });
+        let detail = Rc::new(Detail { label: name.to_owned().into(), max_size: 64, others: Table::new() });
+        factory.register_stored_item(item.clone(), &detail).n_stored += n;
+        item
+    }
+
+    // Records each run by name, and reports `pending` as its craftable demand.
+    struct Probe {
+        name: &'static str,
+        runs: Rc<RefCell<Vec<&'static str>>>,
+        pending: bool,
+    }
+
+    impl Process for Probe {
+        fn run(&self, _: &Factory) -> ChildTask<Result<(), LocalStr>> {
+            self.runs.borrow_mut().push(self.name);
+            spawn(async { Ok(()) })
+        }
+
+        fn has_pending_work(&self, _: &Factory) -> bool { self.pending }
+    }
+
+    // Takes whatever it's given and counts it.
+    struct Sink {
+        name: LocalStr,
+        priority: i32,
+        weight: Option<f64>,
+        n_received: Rc<Cell<i32>>,
+    }
+
+    impl IntoStorage for Sink {
+        type Output = Sink;
+        fn into_storage(self, _: &Factory) -> Rc<RefCell<Self::Output>> { Rc::new(RefCell::new(self)) }
+    }
+
+    impl Storage for Sink {
+        fn name(&self) -> &LocalStr { &self.name }
+        fn stacks(&self) -> &[Option<DetailStack>] { &[] }
+        fn insertion_priority(&self) -> i32 { self.priority }
+        fn weight(&self) -> Option<f64> { self.weight }
+        fn accepts(&self, _: &Item, _: &Detail) -> bool { true }
+        fn update(&self) -> ChildTask<Result<(), LocalStr>> { spawn(async { Ok(()) }) }
+        fn cleanup(&mut self) {}
+        fn deposit_priority(&mut self, _: &Rc<Item>, _: &Rc<Detail>) -> Option<i32> { Some(0) }
+
+        fn deposit(&mut self, stack: &DetailStack, _: usize) -> DepositResult {
+            self.n_received.set(self.n_received.get() + stack.size);
+            DepositResult { n_deposited: stack.size, task: spawn(async { Ok(()) }) }
+        }
+    }
+
+    fn sink(name: &'static str, priority: i32, weight: Option<f64>) -> (Sink, Rc<Cell<i32>>) {
+        let n_received = Rc::<Cell<i32>>::default();
+        (Sink { name: name.into(), priority, weight, n_received: n_received.clone() }, n_received)
+    }
+
+    fn deposit(factory: &Factory, item: &Rc<Item>, size: i32) {
+        let detail = factory.items[item].borrow().detail.clone();
+        let mut tasks = Vec::new();
+        factory.deposit_item(0, DetailStack { item: item.clone(), detail, size }, &mut tasks)
+    }
+
+    fn named(name: &'static str) -> ProcessOptions { ProcessOptions { name: Some(name.into()), ..<_>::default() } }
+
+    #[test]
+    fn interval_skips_cycles_until_due() {
+        with_test_factory(|factory, _| async move {
+            let runs = Rc::<RefCell<Vec<_>>>::default();
+            let options = ProcessOptions { interval: Some(Duration::from_secs(10)), ..named("slow") };
+            factory.borrow_mut().add_process_with(options, Probe { name: "slow", runs: runs.clone(), pending: false });
+            factory.borrow_mut().add_process(Probe { name: "fast", runs: runs.clone(), pending: false });
+            let weak = Rc::downgrade(&factory);
+            run_processes(&weak).await.unwrap();
+            advance(Duration::from_secs(5)).await;
+            run_processes(&weak).await.unwrap();
+            assert_eq!(*runs.borrow(), ["slow", "fast", "fast"]);
+            advance(Duration::from_secs(5)).await;
+            run_processes(&weak).await.unwrap();
+            assert_eq!(*runs.borrow(), ["slow", "fast", "fast", "slow", "fast"]);
+        })
+    }
+
+    #[test]
+    fn adaptive_interval_stretches_when_idle_and_resets_when_busy() {
+        run_local(async {
+            let tui = Rc::<Tui>::default();
+            let mut config = test_config(&tui);
+            config.min_cycle_time = Duration::from_secs(2);
+            config.max_cycle_time = Some(Duration::from_secs(5));
+            let factory = test_factory(config, |_| ());
+            let mut this = factory.borrow_mut();
+            let mut intervals = Vec::new();
+            for busy in [false, false, false, false, true] {
+                this.adapt_cycle_interval(busy);
+                intervals.push(this.cycle_interval.as_secs_f64())
+            }
+            assert_eq!(intervals, [3., 4.5, 5., 5., 2.]);
+        })
+    }
+
+    #[test]
+    fn fixed_interval_ignores_activity() {
+        with_test_factory(|factory, _| async move {
+            let mut this = factory.borrow_mut();
+            this.adapt_cycle_interval(false);
+            assert_eq!(this.cycle_interval, Duration::from_secs(1));
+        })
+    }
+
+    #[test]
+    fn stall_is_reported_after_threshold_cycles() {
+        with_test_factory(|factory, tui| async move {
+            let runs = Rc::<RefCell<Vec<_>>>::default();
+            let mut this = factory.borrow_mut();
+            this.config.stall_cycles = Some(3);
+            this.add_process_with(named("stuck"), Probe { name: "stuck", runs: runs.clone(), pending: true });
+            this.add_process_with(named("idle"), Probe { name: "idle", runs: runs.clone(), pending: false });
+            drop(this);
+            let weak = Rc::downgrade(&factory);
+            let stalls = || logs(&tui).into_iter().filter(|x| x.contains("no progress")).collect::<Vec<_>>();
+            for _ in 0..2 {
+                run_processes(&weak).await.unwrap();
+                advance(Duration::from_secs(1)).await
+            }
+            assert!(stalls().is_empty());
+            run_processes(&weak).await.unwrap();
+            assert_eq!(stalls(), ["stuck: craftable demand but no progress for 3 cycles"]);
+        })
+    }
+
+    #[test]
+    fn reserved_fluid_is_not_consumed() {
+        // 1000 stored with 800 in backup: ordinary inputs only see the 200 above the backup level.
+        assert_eq!(fluid_availability(1000, 800, false, 0), 200);
+        assert_eq!(fluid_availability(500, 800, false, 0), 0);
+        assert_eq!(fluid_availability(1000, 800, true, 0), 1000);
+        assert_eq!(fluid_availability(1000, 800, true, 300), 700);
+        assert_eq!(fluid_availability(1000, 800, false, 300), 0);
+    }
+
+    #[test]
+    fn pause_ignoring_process_keeps_running() {
+        with_test_factory(|factory, _| async move {
+            let runs = Rc::<RefCell<Vec<_>>>::default();
+            let options = ProcessOptions { ignore_pause: true, ..<_>::default() };
+            factory.borrow_mut().add_process_with(options, Probe { name: "trash", runs: runs.clone(), pending: false });
+            factory.borrow_mut().add_process(Probe { name: "craft", runs: runs.clone(), pending: false });
+            let weak = Rc::downgrade(&factory);
+            factory.borrow_mut().set_paused(true);
+            run_processes(&weak).await.unwrap();
+            assert_eq!(*runs.borrow(), ["trash"]);
+            factory.borrow_mut().set_paused(false);
+            run_processes(&weak).await.unwrap();
+            assert_eq!(*runs.borrow(), ["trash", "trash", "craft"]);
+        })
+    }
+
+    #[test]
+    fn background_waits_for_foreground_work() {
+        with_test_factory(|factory, _| async move {
+            let runs = Rc::<RefCell<Vec<_>>>::default();
+            let mut this = factory.borrow_mut();
+            this.config.background_budget = 1.;
+            // Reserves nothing while being run, like any process that reserves its inputs from a spawned task.
+            this.add_process_with(named("craft"), Probe { name: "craft", runs: runs.clone(), pending: true });
+            let options = ProcessOptions { background: true, ..named("defrag") };
+            this.add_process_with(options, Probe { name: "defrag", runs: runs.clone(), pending: false });
+            drop(this);
+            let weak = Rc::downgrade(&factory);
+            run_processes(&weak).await.unwrap();
+            assert_eq!(*runs.borrow(), ["craft"]);
+            factory.borrow_mut().set_process_enabled("craft", false);
+            run_processes(&weak).await.unwrap();
+            assert_eq!(*runs.borrow(), ["craft", "defrag"]);
+        })
+    }
+
+    #[test]
+    fn oversized_transfer_is_warned_about() {
+        with_test_factory(|factory, tui| async move {
+            factory.borrow_mut().config.warn_transfer_size = Some(64);
+            factory.borrow().check_transfer_size("Cobblestone", 64);
+            assert!(logs(&tui).iter().all(|x| !x.contains("unexpectedly large")));
+            factory.borrow().check_transfer_size("Cobblestone", 6400);
+            assert!(logs(&tui).iter().any(|x| x == "unexpectedly large transfer: Cobblestone*6400"));
+        })
+    }
+
+    #[test]
+    fn persistently_full_bus_slot_is_reported() {
+        with_test_factory(|factory, tui| async move {
+            let mut this = factory.borrow_mut();
+            this.config.bus_stuck_cycles = Some(3);
+            let stuck = || logs(&tui).into_iter().filter(|x| x.contains("stuck with")).collect::<Vec<_>>();
+            this.bus_contents.insert(0, ("Bedrock".into(), 1));
+            this.check_stuck_bus();
+            this.check_stuck_bus();
+            assert!(stuck().is_empty());
+            // A slot that empties starts counting again.
+            this.bus_contents.clear();
+            this.check_stuck_bus();
+            this.bus_contents.insert(0, ("Bedrock".into(), 1));
+            this.check_stuck_bus();
+            this.check_stuck_bus();
+            assert!(stuck().is_empty());
+            this.check_stuck_bus();
+            assert_eq!(stuck(), ["bus slot 1 stuck with Bedrock*1 for 3 cycles"]);
+        })
+    }
+
+    #[test]
+    fn deposits_fill_higher_priority_storage_first() {
+        with_test_factory(|factory, _| async move {
+            let (chest, n_chest) = sink("chest", 0, None);
+            let (drawer, n_drawer) = sink("drawer", 1, None);
+            let (spare, n_spare) = sink("spare", 1, None);
+            let mut this = factory.borrow_mut();
+            this.add_storage(chest);
+            this.add_storage(drawer);
+            this.add_storage(spare);
+            drop(this);
+            let item = stock(&mut factory.borrow_mut(), "minecraft:cobblestone", 0);
+            deposit(&factory.borrow(), &item, 64);
+            // The tie between drawer and spare goes to the one listed first.
+            assert_eq!((n_chest.get(), n_drawer.get(), n_spare.get()), (0, 64, 0));
+        })
+    }
+
+    #[test]
+    fn weighted_storages_share_deposits_by_ratio() {
+        with_test_factory(|factory, _| async move {
+            let (light, n_light) = sink("light", 0, Some(1.));
+            let (heavy, n_heavy) = sink("heavy", 0, Some(3.));
+            let (plain, n_plain) = sink("plain", 0, None);
+            let (overflow, n_overflow) = sink("overflow", -1, None);
+            let mut this = factory.borrow_mut();
+            this.add_storage(light);
+            this.add_storage(heavy);
+            this.add_storage(plain);
+            this.add_storage(overflow);
+            drop(this);
+            let item = stock(&mut factory.borrow_mut(), "minecraft:cobblestone", 0);
+            for _ in 0..500 {
+                deposit(&factory.borrow(), &item, 1)
+            }
+            assert_eq!((n_light.get(), n_heavy.get(), n_plain.get(), n_overflow.get()), (100, 300, 100, 0));
+        })
+    }
+
+    #[test]
+    fn fair_scheduling_rotates_first_pick() {
+        for fair_scheduling in [false, true] {
+            with_test_factory(|factory, _| async move {
+                let runs = Rc::<RefCell<Vec<_>>>::default();
+                let mut this = factory.borrow_mut();
+                this.config.fair_scheduling = fair_scheduling;
+                for name in ["a", "b", "c"] {
+                    this.add_process(Probe { name, runs: runs.clone(), pending: false })
+                }
+                drop(this);
+                let weak = Rc::downgrade(&factory);
+                let mut firsts = Vec::new();
+                for _ in 0..3 {
+                    run_processes(&weak).await.unwrap();
+                    firsts.push(runs.borrow()[0]);
+                    assert_eq!(runs.take().len(), 3)
+                }
+                firsts.sort();
+                assert_eq!(firsts, if fair_scheduling { ["a", "b", "c"] } else { ["a", "a", "a"] });
+            })
+        }
+    }
+
+    #[test]
+    fn fluid_buses_are_granted_by_capacity() {
+        with_test_factory(|factory, _| async move {
+            let mut factory = factory.borrow_mut();
+            let fluid_bus_addrs = vec![local_str!("small"), local_str!("large")];
+            factory.config.fluid_bus_accesses = vec![FluidAccess { client: local_str!("main"), fluid_bus_addrs }];
+            factory.config.fluid_bus_capacities = vec![1000, 8000];
+            let _too_big = factory.fluid_bus_allocate(16000);
+            let large = factory.fluid_bus_allocate(4000);
+            let any = factory.fluid_bus_allocate(0);
+            factory.grant_fluid_buses(&mut vec![0, 1]);
+            assert_eq!(large.await, Ok(1));
+            assert_eq!(any.await, Ok(0));
+            assert_eq!(factory.fluid_bus_wait_queue.len(), 1)
+        })
+    }
+
+    #[test]
+    fn cycle_time_is_sent_to_statsd() {
+        with_test_factory(|factory, _| async move {
+            let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+            listener.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            factory.borrow_mut().config.statsd = Some(StatsD::new(&addr).unwrap());
+            factory.borrow().send_cycle_stats(Duration::from_millis(250));
+            let mut packet = [0; 64];
+            let n = listener.recv(&mut packet).unwrap();
+            assert_eq!(std::str::from_utf8(&packet[..n]).unwrap(), "ccremote.cycle_time:250|ms");
+        })
+    }
+
+    #[test]
+    fn fluid_transfer_is_split_by_bus_capacity() {
+        with_test_factory(|factory, _| async move {
+            let mut this = factory.borrow_mut();
+            let fluid_bus_addrs = vec![local_str!("bus")];
+            this.config.fluid_bus_accesses = vec![FluidAccess { client: local_str!("main"), fluid_bus_addrs }];
+            this.config.fluid_bus_capacities = vec![8000];
+            // Stands in for fluid_bus_main, leaving the test to hand out the bus.
+            this.fluid_bus_task = Some(spawn(std::future::pending()));
+            drop(this);
+            let transfers = Rc::<RefCell<Vec<_>>>::default();
+            let task = spawn({
+                let (weak, transfers) = (Rc::downgrade(&factory), transfers.clone());
+                async move {
+                    transfer_via_fluid_buses(&weak, 16000, |bus, qty| {
+                        transfers.borrow_mut().push((bus, qty));
+                        async { Ok(()) }
+                    })
+                    .await
+                }
+            });
+            for _ in 0..2 {
+                yield_now().await;
+                factory.borrow_mut().grant_fluid_buses(&mut vec![0])
+            }
+            task.await.unwrap().unwrap();
+            assert_eq!(*transfers.borrow(), [(0, 8000), (0, 8000)]);
+        })
+    }
+}
@@ -44,7 +44,7 @@ fn fetch_detail<T: Inventory>(this: &T, slot: usize) -> impl Future<Output = Res
         args: vec![if cfg!(feature = "plethora") { "getItemMeta" } else { "getItemDetail" }.into(), (slot + 1).into()],
     });
     server.enqueue_request_group(access.get_client(), vec![action.clone().into()]);
-    async move { DetailStack::parse(call_result(action.await?)?) }
+    async move { call_result::<DetailStack>(action.await?) }
 }
 
 fn fetch_detail_list<T: Inventory>(this: &T) -> impl Future<Output = Result<Vec<Option<DetailStack>>, LocalStr>> {
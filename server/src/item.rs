@@ -1,6 +1,7 @@
-use super::lua_value::{table_remove, Table, Value};
+use super::lua_value::{table_remove, table_remove_opt, Table, Value};
 use flexstr::{local_fmt, LocalStr};
 use hex::{FromHex, ToHex};
+use serde::{ser::SerializeMap, Serialize, Serializer};
 use std::{cmp::min, rc::Rc};
 
 #[derive(PartialEq, Eq, Hash)]
@@ -14,10 +15,7 @@ pub struct Item {
 const NBT_HASH_KEY: &'static str = if cfg!(feature = "plethora") { "nbtHash" } else { "nbt" };
 
 fn remove_nbt_hash(table: &mut Table) -> Result<Option<[u8; 16]>, LocalStr> {
-    table
-        .remove(&NBT_HASH_KEY.into())
-        .map(LocalStr::try_from)
-        .transpose()?
+    table_remove_opt::<LocalStr>(table, NBT_HASH_KEY)?
         .map(|x| <_>::from_hex(&*x))
         .transpose()
         .map_err(|e| local_fmt!("invalid nbt-hash: {}", e))
@@ -41,6 +39,24 @@ impl Item {
         #[cfg(feature = "plethora")]
         table.insert("damage".into(), self.damage.into());
     }
+
+    fn serialize_fields<M: SerializeMap>(&self, map: &mut M) -> Result<(), M::Error> {
+        map.serialize_entry("name", &*self.name)?;
+        if let Some(nbt_hash) = self.nbt_hash {
+            map.serialize_entry("nbt", &nbt_hash.encode_hex::<String>())?;
+        }
+        #[cfg(feature = "plethora")]
+        map.serialize_entry("damage", &self.damage)?;
+        Ok(())
+    }
+}
+
+impl Serialize for Item {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        self.serialize_fields(&mut map)?;
+        map.end()
+    }
 }
 
 pub struct ItemStack {
@@ -103,9 +119,26 @@ impl DetailStack {
     }
 }
 
+impl TryFrom<Value> for DetailStack {
+    type Error = LocalStr;
+    fn try_from(value: Value) -> Result<Self, LocalStr> { Self::parse(value.try_into()?) }
+}
+
+impl Serialize for DetailStack {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        self.item.serialize_fields(&mut map)?;
+        map.serialize_entry("label", &*self.detail.label)?;
+        map.serialize_entry("count", &self.size)?;
+        map.end()
+    }
+}
+
 #[derive(Clone)]
 pub enum Filter {
     Label(LocalStr),
+    // Holds an already normalized label, see `normalize_label`.
+    LabelFuzzy(LocalStr),
     Name(LocalStr),
     Both { label: LocalStr, name: LocalStr },
     Custom { desc: LocalStr, func: Rc<dyn Fn(&Item, &Detail) -> bool> },
@@ -115,11 +148,75 @@ impl Filter {
     pub fn apply(&self, item: &Item, detail: &Detail) -> bool {
         match self {
             Filter::Label(label) => detail.label == *label,
+            Filter::LabelFuzzy(label) => normalize_label(&detail.label) == **label,
             Filter::Name(name) => item.name == *name,
             Filter::Both { label, name } => detail.label == *label && item.name == *name,
             Filter::Custom { func, .. } => func(item, detail),
         }
     }
+
+    pub fn describe(&self) -> LocalStr {
+        match self {
+            Filter::Label(x) => x.clone(),
+            Filter::LabelFuzzy(x) => local_fmt!("~{}", x),
+            Filter::Name(x) => local_fmt!("<{}>", x),
+            Filter::Both { label, name } => local_fmt!("{} <{}>", label, name),
+            Filter::Custom { desc, .. } => local_fmt!("<{}>", desc),
+        }
+    }
+
+    pub fn get_text(&self) -> &LocalStr {
+        match self {
+            Filter::Label(label) | Filter::LabelFuzzy(label) | Filter::Both { label, .. } => label,
+            Filter::Name(name) => name,
+            Filter::Custom { desc, .. } => desc,
+        }
+    }
+}
+
+impl PartialEq for Filter {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Filter::Label(x), Filter::Label(y)) => x == y,
+            (Filter::LabelFuzzy(x), Filter::LabelFuzzy(y)) => x == y,
+            (Filter::Name(x), Filter::Name(y)) => x == y,
+            (Filter::Both { label: xl, name: xn }, Filter::Both { label: yl, name: yn }) => xl == yl && xn == yn,
+            (Filter::Custom { func: x, .. }, Filter::Custom { func: y, .. }) => Rc::ptr_eq(x, y),
+            _ => false,
+        }
+    }
+}
+
+// Lowercases, strips `§` formatting codes, and trims and collapses whitespace.
+pub fn normalize_label(label: &str) -> String {
+    let mut result = String::with_capacity(label.len());
+    let mut chars = label.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            result.extend(c.to_lowercase())
+        }
+    }
+    result.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub struct Relabel {
+    pub from: LocalStr,
+    pub to: LocalStr,
+}
+
+// Rules match on the registry name only; display labels are left as the game reports them.
+pub fn relabel(rules: &[Relabel], item: &Rc<Item>) -> Rc<Item> {
+    match rules.iter().find(|rule| rule.from == item.name) {
+        None => item.clone(),
+        Some(rule) => Rc::new(Item {
+            name: rule.to.clone(),
+            nbt_hash: item.nbt_hash,
+            #[cfg(feature = "plethora")]
+            damage: item.damage,
+        }),
+    }
 }
 
 pub fn jammer() -> DetailStack {
@@ -172,3 +269,56 @@ pub fn insert_into_inventory(
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(name: &str) -> Rc<Item> {
+        Rc::new(Item {
+            name: name.to_owned().into(),
+            nbt_hash: None,
+            #[cfg(feature = "plethora")]
+            damage: 0,
+        })
+    }
+
+    fn detail(label: &str) -> Rc<Detail> {
+        Rc::new(Detail { label: label.to_owned().into(), max_size: 64, others: Table::new() })
+    }
+
+    fn rules() -> Vec<Relabel> { vec![Relabel { from: "oldmod:ingot".into(), to: "newmod:ingot".into() }] }
+
+    #[test]
+    fn relabeled_stock_satisfies_new_name_input() {
+        let old = relabel(&rules(), &item("oldmod:ingot"));
+        let input = Filter::Name("newmod:ingot".into());
+        assert!(input.apply(&old, &detail("Ingot")));
+        // Old and new stock become the same item, so their counts pool together.
+        assert!(old == relabel(&rules(), &item("newmod:ingot")));
+    }
+
+    #[test]
+    fn relabel_leaves_other_names_and_labels_alone() {
+        let other = item("oldmod:dust");
+        assert!(Rc::ptr_eq(&relabel(&rules(), &other), &other));
+        // A label that happens to equal `from` is not a registry name and must not be rewritten.
+        let labeled = item("minecraft:paper");
+        assert!(Filter::Label("oldmod:ingot".into()).apply(&relabel(&rules(), &labeled), &detail("oldmod:ingot")));
+    }
+
+    #[test]
+    fn fuzzy_label_ignores_case_and_whitespace() {
+        let filter = Filter::LabelFuzzy(normalize_label("  Iron   ingot ").into());
+        assert!(filter.apply(&item("minecraft:iron_ingot"), &detail("Iron Ingot")));
+        assert!(filter.apply(&item("minecraft:iron_ingot"), &detail("IRON\tINGOT ")));
+        assert!(!filter.apply(&item("minecraft:iron_ingot"), &detail("Iron Ingots")));
+        assert!(!Filter::Label("iron ingot".into()).apply(&item("minecraft:iron_ingot"), &detail("Iron Ingot")));
+    }
+
+    #[test]
+    fn normalize_label_strips_color_codes() {
+        assert_eq!(normalize_label("§6Gold§r  Nugget"), "gold nugget");
+        assert_eq!(normalize_label("§"), "");
+    }
+}
@@ -0,0 +1,48 @@
+use chrono::Local;
+use flexstr::{local_fmt, LocalStr};
+use std::{
+    fs::{rename, File, OpenOptions},
+    io::Write,
+};
+
+const MAX_SIZE: u64 = 10 << 20;
+const N_ROTATIONS: usize = 3;
+
+pub struct LogFile {
+    path: String,
+    file: File,
+    size: u64,
+}
+
+fn open(path: &str) -> Result<(File, u64), LocalStr> {
+    let file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| local_fmt!("{path}: {e}"))?;
+    let size = file.metadata().map_err(|e| local_fmt!("{path}: {e}"))?.len();
+    Ok((file, size))
+}
+
+impl LogFile {
+    pub fn new(path: String) -> Result<Self, LocalStr> {
+        let (file, size) = open(&path)?;
+        Ok(Self { path, file, size })
+    }
+
+    fn rotate(&mut self) -> Result<(), LocalStr> {
+        for i in (1..N_ROTATIONS).rev() {
+            let _ = rename(format!("{}.{}", self.path, i), format!("{}.{}", self.path, i + 1));
+        }
+        rename(&self.path, format!("{}.1", self.path)).map_err(|e| local_fmt!("{}: {e}", self.path))?;
+        (self.file, self.size) = open(&self.path)?;
+        Ok(())
+    }
+
+    pub fn write(&mut self, msg: &str) {
+        // Failing to rotate just keeps appending to the current file.
+        if self.size >= MAX_SIZE && self.rotate().is_err() {
+            self.size = 0
+        }
+        let line = format!("{} {msg}\n", Local::now().format("%Y-%m-%d %H:%M:%S"));
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.size += line.len() as u64
+        }
+    }
+}
@@ -46,6 +46,45 @@ pub enum Value {
     T(Table),
 }
 
+impl Value {
+    pub fn as_f64(&self) -> Result<f64, LocalStr> {
+        if let Value::F(result) = self {
+            Ok(result.into_inner())
+        } else {
+            Err(local_fmt!("non-numeric: {:?}", self))
+        }
+    }
+
+    pub fn as_int<I>(&self) -> Result<I, LocalStr>
+    where
+        f64: AsPrimitive<I>,
+        I: AsPrimitive<f64>,
+    {
+        try_into_integer(self.as_f64()?)
+    }
+
+    pub fn as_str(&self) -> Result<&str, LocalStr> {
+        if let Value::S(result) = self {
+            Ok(result)
+        } else {
+            Err(local_fmt!("non-string: {:?}", self))
+        }
+    }
+
+    pub fn as_table(&self) -> Result<&Table, LocalStr> {
+        if let Value::T(result) = self {
+            Ok(result)
+        } else {
+            Err(local_fmt!("non-table: {:?}", self))
+        }
+    }
+
+    // Borrowing counterpart of table_remove, for reading a field without taking the table apart.
+    pub fn get_field(&self, name: &'static str) -> Result<&Value, LocalStr> {
+        self.as_table()?.get(&name.into()).ok_or_else(|| local_fmt!("key not found: {}", name))
+    }
+}
+
 impl From<u8> for Value {
     fn from(number: u8) -> Value { Value::F(NotNan::from_u8(number).unwrap()) }
 }
@@ -184,7 +223,29 @@ pub fn call_result<T: TryFrom<Value, Error = LocalStr>>(value: Value) -> Result<
 }
 
 pub fn table_remove<T: TryFrom<Value, Error = LocalStr>>(table: &mut Table, key: &'static str) -> Result<T, LocalStr> {
-    T::try_from(table.remove(&key.into()).ok_or_else(|| local_fmt!("key not found: {}", key))?)
+    let value = table.remove(&key.into()).ok_or_else(|| local_fmt!("key not found: {}", key))?;
+    T::try_from(value).map_err(|e| local_fmt!("{}: {}", key, e))
+}
+
+pub fn table_remove_opt<T>(table: &mut Table, key: &'static str) -> Result<Option<T>, LocalStr>
+where
+    T: TryFrom<Value, Error = LocalStr>,
+{
+    let Some(value) = table.remove(&key.into()) else { return Ok(None) };
+    T::try_from(value).map(Some).map_err(|e| local_fmt!("{}: {}", key, e))
+}
+
+// The result of pushItems/pullItems: a call result holding the number of items moved.
+pub struct TransferResult {
+    pub n_moved: i32,
+}
+
+impl TryFrom<Value> for TransferResult {
+    type Error = LocalStr;
+    fn try_from(value: Value) -> Result<Self, LocalStr> {
+        let n_moved = call_result(value).map_err(|e| local_fmt!("transfer result: {}", e))?;
+        Ok(Self { n_moved })
+    }
 }
 
 fn serialize_string(x: &str, out: &mut Vec<u8>) {
@@ -345,3 +406,65 @@ impl Parser {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::item::DetailStack;
+    use super::*;
+
+    fn table(entries: Vec<(&'static str, Value)>) -> Value {
+        Value::T(entries.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    fn detail_table() -> Value {
+        table(vec![
+            ("name", "minecraft:iron_ingot".into()),
+            ("count", 12.into()),
+            ("displayName", "Iron Ingot".into()),
+            ("maxCount", 64.into()),
+            ("tags", table(vec![("forge:ingots", true.into())])),
+        ])
+    }
+
+    #[test]
+    fn scalar_helpers() {
+        assert_eq!(Value::from(42).as_int::<i32>(), Ok(42));
+        assert!(Value::F(NotNan::new(1.5).unwrap()).as_int::<i32>().unwrap_err().starts_with("non-integer"));
+        assert_eq!(Value::from("stone").as_str(), Ok("stone"));
+        assert!(Value::from(true).as_str().unwrap_err().starts_with("non-string"));
+    }
+
+    #[test]
+    fn get_field_reads_without_consuming() {
+        let value = detail_table();
+        assert_eq!(value.get_field("name").and_then(Value::as_str), Ok("minecraft:iron_ingot"));
+        assert_eq!(value.get_field("count").and_then(Value::as_int::<i32>), Ok(12));
+        assert!(value.get_field("tags").and_then(|x| x.get_field("forge:ingots")).is_ok());
+        assert_eq!(&*value.get_field("nbt").unwrap_err(), "key not found: nbt");
+        assert!(Value::from(3).get_field("name").unwrap_err().starts_with("non-table"));
+    }
+
+    #[test]
+    fn detail_stack_shape() {
+        let stack = DetailStack::try_from(detail_table()).unwrap();
+        assert_eq!(&*stack.item.name, "minecraft:iron_ingot");
+        assert_eq!((stack.size, stack.detail.max_size), (12, 64));
+        assert_eq!(&*stack.detail.label, "Iron Ingot");
+        assert!(stack.detail.others.contains_key(&Key::from("tags")));
+
+        let mut wrong = detail_table();
+        let Value::T(entries) = &mut wrong else { unreachable!() };
+        entries.insert("count".into(), "twelve".into());
+        assert!(DetailStack::try_from(wrong).err().unwrap().starts_with("count: non-numeric"));
+    }
+
+    #[test]
+    fn transfer_result_shape() {
+        let result = Value::T(vec_to_table(vec![7.into()]));
+        assert_eq!(TransferResult::try_from(result).unwrap().n_moved, 7);
+        let empty = Value::T(Table::new());
+        assert!(TransferResult::try_from(empty).err().unwrap().starts_with("transfer result: invalid call result"));
+        let text = Value::T(vec_to_table(vec!["oops".into()]));
+        assert!(TransferResult::try_from(text).err().unwrap().starts_with("transfer result: non-numeric"));
+    }
+}
@@ -8,55 +8,103 @@ pub mod recipe;
 pub mod config_util;
 pub mod access;
 pub mod action;
+pub mod api;
+pub mod command;
 pub mod config;
 pub mod detail_cache;
 pub mod factory;
 pub mod item;
+pub mod log_file;
 pub mod lua_value;
 pub mod process;
 pub mod server;
+pub mod statsd;
 pub mod storage;
 pub mod turtle_rc;
+pub mod webhook;
 
-use config::build_factory;
+use chrono::Local;
+use config::{build_factory, build_reloadable_factory};
 use crossterm::{
-    event::{Event, EventStream},
+    event::{DisableMouseCapture, EnableMouseCapture, Event, EventStream, MouseEventKind},
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
+use factory::{CycleStatus, FactorySlot};
 use futures_util::StreamExt;
+use log_file::LogFile;
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Layout, Margin},
-    style::Color,
-    text::Line,
+    layout::{Constraint, Layout, Margin, Position, Rect},
+    style::{Color, Stylize},
+    text::{Line, Span},
     widgets::{Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState},
     Frame, Terminal,
 };
 use std::{
     cell::{Cell, RefCell},
     collections::VecDeque,
-    io::{self, Write},
+    io::{self, IsTerminal, Write},
     rc::Rc,
+    sync::Mutex,
+    time::SystemTime,
 };
 use tokio::{select, sync::Notify, task::LocalSet};
 use tui_textarea::{CursorMove, Input, Key, TextArea};
 
+const TIME_FORMAT: &str = "%H:%M:%S ";
+const MAX_LOG_LINES: usize = 1000;
+
+// Where log lines go besides the in-memory buffer. Only the screen variant is ever drawn.
+#[derive(Default)]
+enum LogOutput {
+    #[default]
+    Screen,
+    Stdout {
+        json: bool,
+    },
+}
+
 #[derive(Default)]
 pub struct Tui {
+    output: LogOutput,
     on_redraw: Notify,
     on_input: Notify,
     logs: RefCell<VecDeque<Line<'static>>>,
+    log_file: RefCell<Option<LogFile>>,
+    log_area: Cell<Rect>,
+    // Index of the top visible log line, or None to follow the newest lines.
+    log_scroll: Cell<Option<usize>>,
     input_queue: RefCell<Vec<String>>,
     text_area: RefCell<TextArea<'static>>,
     main_list: RefCell<Vec<Line<'static>>>,
+    main_filter: RefCell<String>,
+    main_list_shown: RefCell<Vec<Line<'static>>>,
     main_scroll: Cell<u16>,
     main_scroll_state: RefCell<ScrollbarState>,
+    main_list_area: Cell<Rect>,
+    main_list_pinned: Cell<bool>,
+    history: RefCell<Vec<String>>,
+    history_pos: Cell<Option<usize>>,
 }
 
 impl Tui {
+    // For running without a terminal: logs go to stdout, as JSON lines if LOG_FORMAT=json.
+    fn headless() -> Self {
+        let json = std::env::var("LOG_FORMAT").is_ok_and(|x| x == "json");
+        Self { output: LogOutput::Stdout { json }, ..Self::default() }
+    }
+
     fn request_redraw(&self) { self.on_redraw.notify_one() }
     fn log(&self, msg: String, color: u8) {
+        match self.output {
+            LogOutput::Screen => (),
+            LogOutput::Stdout { json: true } => {
+                let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+                println!("{}", serde_json::json!({ "ts": ts, "level": log_level(color), "msg": msg }))
+            }
+            LogOutput::Stdout { json: false } => println!("{}{}", Local::now().format(TIME_FORMAT), msg),
+        }
         let color = match color {
             0 => Color::Reset,
             1 => Color::LightYellow,
@@ -65,38 +113,145 @@ impl Tui {
             10 => Color::LightMagenta,
             13 => Color::Green,
             14 => Color::Red,
-            _ => unreachable!(),
+            _ => Color::Indexed(color),
         };
-        self.logs.borrow_mut().push_back(Line::styled(msg, color));
+        if let Some(log_file) = &mut *self.log_file.borrow_mut() {
+            log_file.write(&msg)
+        }
+        let time = Span::raw(Local::now().format(TIME_FORMAT).to_string()).dim();
+        let mut logs = self.logs.borrow_mut();
+        logs.push_back(Line::from(vec![time, Span::styled(msg, color)]));
+        if logs.len() > MAX_LOG_LINES {
+            logs.pop_front();
+            self.log_scroll.set(self.log_scroll.get().map(|top| top.saturating_sub(1)))
+        }
         self.request_redraw()
     }
 
+    fn scroll_log(&self, upd: impl FnOnce(usize) -> usize) {
+        let tail = self.logs.borrow().len().saturating_sub(self.log_area.get().height as _);
+        let top = upd(self.log_scroll.get().unwrap_or(tail));
+        self.log_scroll.set(Some(top).filter(|&top| top < tail));
+        self.request_redraw()
+    }
+
+    fn toggle_log_follow(&self) {
+        if self.log_scroll.get().is_some() {
+            self.log_scroll.set(None)
+        } else {
+            self.log_scroll.set(Some(self.logs.borrow().len().saturating_sub(self.log_area.get().height as _)))
+        }
+        self.request_redraw()
+    }
+
+    fn set_log_file(&self, log_file: LogFile) { *self.log_file.borrow_mut() = Some(log_file) }
+
+    pub fn save_log(&self, path: &str) -> io::Result<usize> {
+        let logs = self.logs.borrow();
+        let mut text = String::new();
+        for line in &*logs {
+            line.spans.iter().for_each(|span| text += &span.content);
+            text.push('\n')
+        }
+        std::fs::write(path, text)?;
+        Ok(logs.len())
+    }
+
     fn set_main_list(&self, list: Vec<Line<'static>>) {
         *self.main_list.borrow_mut() = list;
+        self.apply_main_filter()
+    }
+
+    fn show_main_list(&self, list: Vec<Line<'static>>) {
+        self.main_list_pinned.set(true);
+        self.main_scroll.set(0);
+        self.set_main_list(list);
+        self.request_redraw()
+    }
+
+    fn unpin_main_list(&self) {
+        self.main_list_pinned.set(false);
+        self.set_main_list(Vec::new())
+    }
+
+    fn set_main_filter(&self, filter: &str) {
+        *self.main_filter.borrow_mut() = filter.trim().to_lowercase();
+        self.apply_main_filter()
+    }
+
+    fn apply_main_filter(&self) {
+        let filter = self.main_filter.borrow();
+        *self.main_list_shown.borrow_mut() = (self.main_list.borrow().iter())
+            .filter(|line| filter.is_empty() || line_text(line).to_lowercase().contains(&*filter))
+            .cloned()
+            .collect();
         self.set_main_scroll(|x| x)
     }
 
     fn set_main_scroll(&self, upd: impl FnOnce(u16) -> u16) {
-        let list = self.main_list.borrow();
-        let i = upd(self.main_scroll.get());
-        self.main_scroll.set(i.min(list.len().max(1) as u16 - 1));
+        let list = self.main_list_shown.borrow();
+        let i = upd(self.main_scroll.get()).min(list.len().max(1) as u16 - 1);
+        self.main_scroll.set(i);
         let mut state = self.main_scroll_state.borrow_mut();
         *state = state.position(i as _).content_length(list.len())
     }
 
-    fn frame(&self, frame: &mut Frame) {
-        let layout = Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).split(frame.area());
-        frame.render_widget(&*self.text_area.borrow(), layout[1]);
+    fn submit_input(&self) {
+        let mut text_area = self.text_area.borrow_mut();
+        if let Some(line) = text_area.lines().get(text_area.cursor().0).cloned() {
+            let mut history = self.history.borrow_mut();
+            if !line.is_empty() && history.last() != Some(&line) {
+                history.push(line.clone())
+            }
+            if let Some(filter) = line.strip_prefix('/') {
+                self.set_main_filter(filter)
+            } else {
+                self.input_queue.borrow_mut().push(line)
+            }
+        }
+        self.history_pos.set(None);
+        text_area.move_cursor(CursorMove::End);
+        text_area.insert_newline()
+    }
+
+    fn recall_history(&self, older: bool) -> bool {
+        let mut text_area = self.text_area.borrow_mut();
+        if text_area.cursor().0 + 1 != text_area.lines().len() {
+            return false;
+        }
+        let history = self.history.borrow();
+        let pos = match (self.history_pos.get(), older) {
+            (None, true) => history.len().checked_sub(1),
+            (None, false) => return true,
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) => Some(i + 1).filter(|&i| i < history.len()),
+        };
+        self.history_pos.set(pos);
+        text_area.move_cursor(CursorMove::End);
+        text_area.delete_line_by_head();
+        if let Some(pos) = pos {
+            text_area.insert_str(&history[pos]);
+        }
+        true
+    }
+
+    fn frame(&self, frame: &mut Frame, status: &CycleStatus) {
+        let layout =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(1), Constraint::Length(1)]).split(frame.area());
+        frame.render_widget(status_line(status, self.log_scroll.get().is_none()), layout[1]);
+        frame.render_widget(&*self.text_area.borrow(), layout[2]);
 
         let log_size;
-        let main_list = self.main_list.borrow();
-        if main_list.is_empty() {
-            log_size = layout[0]
+        if self.main_list.borrow().is_empty() {
+            log_size = layout[0];
+            self.main_list_area.set(Rect::default())
         } else {
             let layout = Layout::horizontal([Constraint::Percentage(50), Constraint::Fill(1)]).split(layout[0]);
             log_size = layout[0];
             let main_list_size = layout[1];
-            frame.render_widget(Paragraph::new(main_list.clone()).scroll((self.main_scroll.get(), 0)), main_list_size);
+            self.main_list_area.set(main_list_size);
+            let main_list = self.main_list_shown.borrow().clone();
+            frame.render_widget(Paragraph::new(main_list).scroll((self.main_scroll.get(), 0)), main_list_size);
             let scroll = Scrollbar::new(ScrollbarOrientation::VerticalRight);
             frame.render_stateful_widget(
                 scroll,
@@ -105,105 +260,218 @@ impl Tui {
             )
         }
 
-        let mut log_buffer = self.logs.borrow_mut();
-        while log_buffer.len() > log_size.height as _ {
-            log_buffer.pop_front();
-        }
-        frame.render_widget(Paragraph::new(Vec::from_iter(log_buffer.iter().cloned())), log_size)
+        self.log_area.set(log_size);
+        let log_buffer = self.logs.borrow();
+        let height = log_size.height as usize;
+        let tail = log_buffer.len().saturating_sub(height);
+        let top = self.log_scroll.get().map_or(tail, |top| top.min(tail));
+        frame.render_widget(Paragraph::new(Vec::from_iter(log_buffer.range(top..).take(height).cloned())), log_size)
     }
 }
 
-struct NonInteractiveTui {
-    logs: RefCell<VecDeque<String>>,
-}
+fn line_text(line: &Line) -> String { line.spans.iter().map(|x| x.content.as_ref()).collect() }
 
-impl NonInteractiveTui {
-    fn new() -> Self {
-        Self {
-            logs: RefCell::new(VecDeque::new()),
+fn status_line(status: &CycleStatus, follow: bool) -> Line<'static> {
+    let cycle_time = if let Some(last) = status.last_cycle_time {
+        let span = Span::raw(format!("cycle {:.3}s", last.as_secs_f64()));
+        if last > status.min_cycle_time + status.min_cycle_time / 2 {
+            span.red()
+        } else {
+            span
         }
+    } else {
+        Span::raw("cycle -")
+    };
+    Line::from(vec![
+        cycle_time,
+        Span::raw(format!(" / {:.3}s, {} clients", status.cycle_interval.as_secs_f64(), status.n_clients)),
+        Span::raw(if follow { ", log follow" } else { ", log paused" }),
+        if status.paused { Span::raw(", factory paused").yellow() } else { Span::raw("") },
+    ])
+    .reversed()
+}
+
+fn log_level(color: u8) -> &'static str {
+    match color {
+        6 | 14 => "error",
+        1 => "warn",
+        _ => "info",
     }
+}
 
-    fn log(&self, msg: String, _color: u8) {
-        println!("{}", msg);
-        self.logs.borrow_mut().push_back(msg);
-        if self.logs.borrow().len() > 1000 {
-            self.logs.borrow_mut().pop_front();
-        }
+// Validates the config at `path` without binding the server port, returning the process exit code.
+fn check_config(path: &str) -> i32 {
+    let problems = match config::try_load_dynamic_config(path) {
+        Ok(config) => config::check_config(&config),
+        Err(e) => vec![e],
+    };
+    for problem in &problems {
+        println!("{}", problem)
+    }
+    if problems.is_empty() {
+        println!("{path}: ok");
+        0
+    } else {
+        println!("{path}: {} problems", problems.len());
+        1
     }
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    // Try to determine if we're running in an interactive terminal
-    let is_interactive = crossterm::terminal::is_terminal(io::stdout());
+    let mut args = std::env::args().skip_while(|x| x != "--check");
+    if args.next().is_some() {
+        let Some(path) = args.next().or_else(|| std::env::var("CONFIG_PATH").ok()) else {
+            eprintln!("usage: --check <config path>, or set CONFIG_PATH");
+            std::process::exit(2)
+        };
+        std::process::exit(check_config(&path))
+    }
+
+    // --tui and --no-tui override guessing from whether stdout is a terminal, and are mutually exclusive.
+    let force_tui = std::env::args().any(|x| x == "--tui");
+    let force_no_tui = std::env::args().any(|x| x == "--no-tui");
+    let is_interactive = match (force_tui, force_no_tui) {
+        (true, true) => {
+            eprintln!("--tui and --no-tui are mutually exclusive");
+            std::process::exit(2)
+        }
+        (true, false) => true,
+        (false, true) => false,
+        (false, false) => io::stdout().is_terminal(),
+    };
 
+    // --dry-run starts with transfers logged only; the server keeps it across reloads.
+    let dry_run = std::env::args().any(|x| x == "--dry-run");
     if is_interactive {
-        run_interactive().await;
+        run_interactive(dry_run).await;
     } else {
-        run_noninteractive().await;
+        run_noninteractive(dry_run).await;
     }
 }
 
-async fn run_interactive() {
+fn set_dry_run(factory: &FactorySlot, dry_run: bool) {
+    let factory = factory.lock().unwrap();
+    factory.as_ref().unwrap().borrow().config.server.borrow_mut().dry_run = dry_run;
+}
+
+async fn run_interactive(dry_run: bool) {
     let tasks = LocalSet::new();
-    tasks.spawn_local(async {
+    tasks.spawn_local(async move {
         enable_raw_mode().unwrap();
         stdout().execute(EnterAlternateScreen).unwrap();
+        stdout().execute(EnableMouseCapture).unwrap();
         let mut evts = EventStream::new();
         let mut term = Terminal::new(CrosstermBackend::new(std::io::stderr())).unwrap();
         let tui = Rc::<Tui>::default();
         // To run turtle_rc, replace with:
         // let _factory = turtle_rc::run(server::Server::new(tui.clone(), 1848));
-        let _factory = build_factory(tui.clone());
+        let factory = match std::env::var("CONFIG_PATH") {
+            Ok(path) => build_reloadable_factory(tui.clone(), &path),
+            Err(_) => FactorySlot::new(Mutex::new(Some(build_factory(tui.clone())))),
+        };
+        set_dry_run(&factory, dry_run);
         loop {
-            term.draw(|frame| tui.frame(frame)).unwrap();
+            let status = factory.lock().unwrap().as_ref().unwrap().borrow().get_cycle_status();
+            term.draw(|frame| tui.frame(frame, &status)).unwrap();
             let evt = select! {
                 () = tui.on_redraw.notified() => None,
                 evt = evts.next() => if let Some(Ok(x)) = evt { Some(x) } else { break }
             };
-            if let Some(Event::Key(evt)) = evt {
+            if let Some(Event::Mouse(evt)) = evt {
+                if tui.main_list_area.get().contains(Position::new(evt.column, evt.row)) {
+                    match evt.kind {
+                        MouseEventKind::ScrollUp => tui.set_main_scroll(|x| x.saturating_sub(2)),
+                        MouseEventKind::ScrollDown => tui.set_main_scroll(|x| x.saturating_add(2)),
+                        _ => (),
+                    }
+                } else if tui.log_area.get().contains(Position::new(evt.column, evt.row)) {
+                    match evt.kind {
+                        MouseEventKind::ScrollUp => tui.scroll_log(|x| x.saturating_sub(2)),
+                        MouseEventKind::ScrollDown => tui.scroll_log(|x| x.saturating_add(2)),
+                        _ => (),
+                    }
+                }
+            } else if let Some(Event::Key(evt)) = evt {
                 let evt = Input::from(evt);
                 if evt.ctrl && (evt.key == Key::Char('c') || evt.key == Key::Char('d')) {
                     break;
                 } else if evt.ctrl && evt.key == Key::Char('l') {
-                    tui.logs.borrow_mut().clear()
+                    tui.logs.borrow_mut().clear();
+                    tui.log_scroll.set(None)
+                } else if evt.ctrl && evt.key == Key::End {
+                    tui.toggle_log_follow()
+                } else if evt.key == Key::Esc {
+                    tui.unpin_main_list()
                 } else if evt.key == Key::PageUp {
                     tui.set_main_scroll(|x| x.saturating_sub(8))
                 } else if evt.key == Key::PageDown {
                     tui.set_main_scroll(|x| x.saturating_add(8))
                 } else if evt.ctrl && evt.key == Key::Char('m') || evt.key == Key::Enter {
-                    let mut text_area = tui.text_area.borrow_mut();
-                    tui.input_queue.borrow_mut().extend(text_area.lines().get(text_area.cursor().0).cloned());
-                    text_area.move_cursor(CursorMove::End);
-                    text_area.insert_newline()
-                } else {
+                    tui.submit_input()
+                } else if !(matches!(evt.key, Key::Up | Key::Down) && tui.recall_history(evt.key == Key::Up)) {
                     tui.text_area.borrow_mut().input(evt);
                 }
                 tui.on_input.notify_waiters()
             }
         }
         disable_raw_mode().unwrap();
+        stdout().execute(DisableMouseCapture).unwrap();
         stdout().execute(LeaveAlternateScreen).unwrap();
     });
     tasks.await;
 }
 
-async fn run_noninteractive() {
-    let tui = Rc::new(NonInteractiveTui::new());
-    println!("Starting CCRemote in non-interactive mode...");
-    
-    // Load config and start factory
-    let factory = match std::env::var("CONFIG_PATH") {
-        Ok(path) => build_factory_from_json(tui, &path),
-        Err(_) => {
-            println!("No CONFIG_PATH specified, using default configuration");
-            build_factory(tui)
-        }
-    };
+async fn run_noninteractive(dry_run: bool) {
+    let tasks = LocalSet::new();
+    tasks.spawn_local(async move {
+        let tui = Rc::new(Tui::headless());
+        println!("Starting CCRemote in non-interactive mode...");
+
+        // Load config and start factory
+        let factory = match std::env::var("CONFIG_PATH") {
+            Ok(path) => build_reloadable_factory(tui.clone(), &path),
+            Err(_) => {
+                println!("No CONFIG_PATH specified, using default configuration");
+                FactorySlot::new(Mutex::new(Some(build_factory(tui.clone()))))
+            }
+        };
+        set_dry_run(&factory, dry_run);
+
+        shutdown_signal().await;
+        println!("Shutting down...");
+        // Dropping the factory aborts its tasks and lets the detail cache write itself out.
+        drop(factory)
+    });
+    tasks.await;
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    select! {
+        _ = sigterm.recv() => (),
+        _ = tokio::signal::ctrl_c() => (),
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() { tokio::signal::ctrl_c().await.unwrap() }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Keep the application running
-    loop {
-        tokio::time::sleep(Duration::from_secs(1)).await;
+    #[test]
+    fn scrolling_empty_main_list_stays_at_top() {
+        let tui = Tui::default();
+        tui.set_main_scroll(|x| x.saturating_add(8));
+        assert_eq!(tui.main_scroll.get(), 0);
+        tui.set_main_list(vec![Line::from("a"), Line::from("b")]);
+        tui.set_main_scroll(|x| x.saturating_add(8));
+        assert_eq!(tui.main_scroll.get(), 1);
+        tui.set_main_list(Vec::new());
+        assert_eq!(tui.main_scroll.get(), 0);
     }
 }
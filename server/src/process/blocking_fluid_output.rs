@@ -49,13 +49,14 @@ impl Process for BlockingFluidOutputProcess {
                     let bus = {
                         alive!(weak, this);
                         upgrade_mut!(this.factory, factory);
-                        factory.fluid_bus_allocate()
+                        factory.fluid_bus_allocate(0)
                     };
                     let bus = bus.await?;
                     let task;
                     {
                         alive!(weak, this);
                         upgrade!(this.factory, factory);
+                        let qty = qty.min(factory.get_fluid_bus_capacity(bus));
                         let server = factory.get_server().borrow();
                         let access = server.load_balance(&this.config.accesses);
                         task = ActionFuture::from(Call {
@@ -178,6 +178,8 @@ impl Process for BufferedProcess {
             join_tasks(tasks).await
         })
     }
+
+    fn has_pending_work(&self, factory: &Factory) -> bool { !compute_demands(factory, &self.config.recipes).is_empty() }
 }
 
 impl BufferedProcess {
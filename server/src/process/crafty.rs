@@ -275,4 +275,6 @@ impl Process for CraftyProcess {
             join_tasks(tasks).await
         })
     }
+
+    fn has_pending_work(&self, factory: &Factory) -> bool { !compute_demands(factory, &self.config.recipes).is_empty() }
 }
@@ -0,0 +1,79 @@
+use super::super::access::BusAccess;
+use super::super::detail_cache::DetailCache;
+use super::super::factory::Factory;
+use super::super::inventory::{list_inventory, Inventory};
+use super::super::item::Filter;
+use super::super::server::Server;
+use super::super::util::{alive, join_tasks, spawn};
+use super::{scattering_insert, IntoProcess, Process};
+use abort_on_drop::ChildTask;
+use flexstr::LocalStr;
+use std::{
+    cell::RefCell,
+    iter::once,
+    rc::{Rc, Weak},
+};
+
+pub struct ExportTarget {
+    pub item: Filter,
+    pub slot: usize,
+    pub n_wanted: i32,
+}
+
+pub struct ExportConfig {
+    pub name: LocalStr,
+    pub accesses: Vec<BusAccess>,
+    pub targets: Vec<ExportTarget>,
+}
+
+pub struct ExportProcess {
+    weak: Weak<RefCell<ExportProcess>>,
+    config: ExportConfig,
+    detail_cache: Rc<RefCell<DetailCache>>,
+    factory: Weak<RefCell<Factory>>,
+    server: Rc<RefCell<Server>>,
+    size: Option<usize>,
+}
+
+impl_inventory!(ExportProcess, BusAccess);
+impl_into_process!(ExportConfig, ExportProcess);
+
+impl Process for ExportProcess {
+    fn run(&self, _factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
+        let stacks = list_inventory(self);
+        let weak = self.weak.clone();
+        spawn(async move {
+            let stacks = stacks.await?;
+            let mut tasks = Vec::new();
+            {
+                alive!(weak, this);
+                upgrade_mut!(this.factory, factory);
+                for target in &this.config.targets {
+                    let Some(stack) = stacks.get(target.slot) else { continue };
+                    // A slot holding something the target doesn't match is left alone rather than cleared.
+                    let (item, n_present) = match stack {
+                        Some(stack) if target.item.apply(&stack.item, &stack.detail) => {
+                            (stack.item.clone(), stack.size)
+                        }
+                        Some(_) => continue,
+                        None => match factory.search_item(&target.item) {
+                            Some((item, _)) => (item.clone(), 0),
+                            None => continue,
+                        },
+                    };
+                    let Some(info) = factory.items.get(&item) else { continue };
+                    let info = info.borrow();
+                    let n_shortfall = target.n_wanted.min(info.detail.max_size) - n_present;
+                    let n_moved = info.get_availability(false, 0).min(n_shortfall);
+                    drop(info);
+                    if n_moved <= 0 {
+                        continue;
+                    }
+                    let reservation = factory.reserve_item(&this.config.name, &item, n_moved);
+                    tasks.push(scattering_insert(this, factory, reservation, once((target.slot, n_moved))))
+                }
+            }
+            join_tasks(tasks).await
+        })
+    }
+}
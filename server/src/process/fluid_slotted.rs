@@ -2,7 +2,7 @@ use super::{EachInv, EachInvConfig, IntoProcess, MultiInvExtractFilter, MultiInv
 use crate::{
     access::{BusAccess, InvTankAccess},
     action::{ActionFuture, Call},
-    factory::{read_tanks, tanks_to_fluid_map, Factory},
+    factory::{read_tanks, tanks_to_fluid_map, transfer_via_fluid_buses, Factory},
     inventory::{list_inventory, Inventory},
     item::DetailStack,
     process::extract_output,
@@ -15,6 +15,7 @@ use flexstr::LocalStr;
 use fnv::{FnvHashMap, FnvHashSet};
 use std::{
     cell::RefCell,
+    cmp::Reverse,
     collections::{hash_map::Entry, BTreeMap},
     rc::{Rc, Weak},
 };
@@ -147,13 +148,19 @@ struct InputInfo {
     n_needed: i64,
 }
 
+// Each input goes over a bus of its own, so the biggest inputs are paired with the biggest buses.
+fn fluid_bus_bound(mut capacities: Vec<i64>, mut sizes: Vec<i64>) -> i64 {
+    capacities.sort_unstable_by(|x, y| y.cmp(x));
+    sizes.sort_unstable_by(|x, y| y.cmp(x));
+    capacities.iter().zip(&sizes).map(|(capacity, size)| capacity / size).min().unwrap_or(i64::MAX)
+}
+
 fn compute_fluid_demands(factory: &Factory, recipes: &[FluidSlottedRecipe]) -> Vec<Demand> {
     let mut result = Vec::new();
     for (i_recipe, recipe) in recipes.iter().enumerate() {
         let Some(mut priority) = recipe.get_outputs().get_priority(factory) else { continue };
         let Some(mut inputs) = resolve_inputs(factory, recipe) else { continue };
         let mut infos = FnvHashMap::<LocalStr, InputInfo>::default();
-        let mut bus_bound = i64::MAX;
         for input in &recipe.fluids {
             match infos.entry(input.fluid.clone()) {
                 Entry::Occupied(input_info) => input_info.into_mut().n_needed += input.size,
@@ -169,8 +176,9 @@ fn compute_fluid_demands(factory: &Factory, recipes: &[FluidSlottedRecipe]) -> V
                     });
                 }
             }
-            bus_bound = bus_bound.min(factory.config.fluid_bus_capacity / input.size)
         }
+        let sizes = recipe.fluids.iter().map(|x| x.size).collect();
+        let bus_bound = fluid_bus_bound(factory.config.fluid_bus_capacities.clone(), sizes);
         let mut availability_bound = i64::MAX;
         for (_, input_info) in infos {
             availability_bound = availability_bound.min(input_info.n_available / input_info.n_needed)
@@ -306,42 +314,40 @@ impl Process for FluidSlottedProcess {
 impl FluidSlottedProcess {
     fn extract_fluids(
         &self,
-        factory: &mut Factory,
+        factory: &Factory,
         i: usize,
         fluids: FnvHashMap<LocalStr, i64>,
         tasks: &mut Vec<ChildTask<Result<(), LocalStr>>>,
     ) {
-        for (fluid, mut remain) in fluids {
-            while remain > 0 {
-                let bus = factory.fluid_bus_allocate();
-                let weak = self.weak.clone();
-                let fluid = fluid.clone();
-                let qty = remain.min(factory.config.fluid_bus_capacity);
-                remain -= qty;
-                tasks.push(spawn(async move {
-                    let bus = bus.await?;
-                    let task;
-                    {
-                        alive!(weak, this);
-                        upgrade!(this.factory, factory);
-                        let server = factory.get_server().borrow();
-                        let access = server.load_balance(&this.accesses);
-                        task = ActionFuture::from(Call {
-                            addr: access.fluid_bus_addrs[bus].clone(),
-                            args: vec![
-                                "pullFluid".into(),
-                                access.tank_addrs[i].clone().into(),
-                                qty.into(),
-                                fluid.into(),
-                            ],
-                        });
-                        server.enqueue_request_group(&access.client, vec![task.clone().into()])
+        for (fluid, n) in fluids {
+            let weak = self.weak.clone();
+            let factory = factory.get_weak().clone();
+            tasks.push(spawn(async move {
+                transfer_via_fluid_buses(&factory, n, |bus, qty| {
+                    let weak = weak.clone();
+                    let fluid = fluid.clone();
+                    async move {
+                        let task;
+                        {
+                            alive!(weak, this);
+                            let server = this.server.borrow();
+                            let access = server.load_balance(&this.accesses);
+                            task = ActionFuture::from(Call {
+                                addr: access.fluid_bus_addrs[bus].clone(),
+                                args: vec![
+                                    "pullFluid".into(),
+                                    access.tank_addrs[i].clone().into(),
+                                    qty.into(),
+                                    fluid.into(),
+                                ],
+                            });
+                            server.enqueue_request_group(&access.client, vec![task.clone().into()])
+                        }
+                        task.await.map(|_| ())
                     }
-                    let result = task.await.map(|_| ());
-                    alive(&weak)?.borrow().factory.upgrade().unwrap().borrow_mut().fluid_bus_deposit([bus]);
-                    result
-                }))
-            }
+                })
+                .await
+            }))
         }
     }
 
@@ -363,10 +369,17 @@ impl FluidSlottedProcess {
                 extraction.await.map(|_| bus_slot)
             }))
         }
-        for input in &recipe.fluids {
+        // Biggest first, so each takes the smallest bus it fits on and the pairing fluid_bus_bound assumed holds.
+        let mut allocations = Vec::from_iter(recipe.fluids.iter().map(|_| None));
+        let mut order = Vec::from_iter(0..recipe.fluids.len());
+        order.sort_by_key(|&i| Reverse(recipe.fluids[i].size));
+        for i in order {
+            allocations[i] = Some(factory.fluid_bus_allocate(recipe.fluids[i].size * demand.inputs.n_sets as i64))
+        }
+        for (input, fluid_bus) in recipe.fluids.iter().zip(allocations) {
             let reservation =
                 factory.reserve_fluid(&self.name, &*input.fluid, input.size * demand.inputs.n_sets as i64);
-            let fluid_bus = factory.fluid_bus_allocate();
+            let fluid_bus = fluid_bus.unwrap();
             let fluid_buses_to_free = fluid_buses_to_free.clone();
             fluid_buses.push(spawn(async move {
                 let fluid_bus = fluid_bus.await?;
@@ -445,3 +458,16 @@ impl FluidSlottedProcess {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bus_bound_pairs_big_inputs_with_big_buses() {
+        assert_eq!(fluid_bus_bound(vec![1000, 8000], vec![1000]), 8);
+        assert_eq!(fluid_bus_bound(vec![1000, 8000], vec![250, 2000]), 4);
+        assert_eq!(fluid_bus_bound(vec![8000, 1000], vec![1000, 250]), 4);
+        assert_eq!(fluid_bus_bound(Vec::new(), vec![1000]), i64::MAX);
+    }
+}
@@ -0,0 +1,56 @@
+use super::super::access::BusAccess;
+use super::super::detail_cache::DetailCache;
+use super::super::factory::Factory;
+use super::super::inventory::{list_inventory, Inventory};
+use super::super::item::Filter;
+use super::super::server::Server;
+use super::super::util::{alive, join_tasks, spawn};
+use super::{extract_output, IntoProcess, Process};
+use abort_on_drop::ChildTask;
+use flexstr::LocalStr;
+use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
+
+pub struct ImportConfig {
+    pub accesses: Vec<BusAccess>,
+    // Items matching any of these are pulled into storage; empty imports everything.
+    pub filters: Vec<Filter>,
+}
+
+pub struct ImportProcess {
+    weak: Weak<RefCell<ImportProcess>>,
+    config: ImportConfig,
+    detail_cache: Rc<RefCell<DetailCache>>,
+    factory: Weak<RefCell<Factory>>,
+    server: Rc<RefCell<Server>>,
+    size: Option<usize>,
+}
+
+impl_inventory!(ImportProcess, BusAccess);
+impl_into_process!(ImportConfig, ImportProcess);
+
+impl Process for ImportProcess {
+    fn run(&self, _factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
+        let stacks = list_inventory(self);
+        let weak = self.weak.clone();
+        spawn(async move {
+            let stacks = stacks.await?;
+            let mut tasks = Vec::new();
+            {
+                alive!(weak, this);
+                upgrade_mut!(this.factory, factory);
+                for (slot, stack) in stacks.iter().enumerate() {
+                    let Some(stack) = stack else { continue };
+                    let filters = &this.config.filters;
+                    if filters.is_empty() || filters.iter().any(|x| x.apply(&stack.item, &stack.detail)) {
+                        // Imported items go through the bus like any other output, so storage rules and trash apply.
+                        tasks.push(extract_output(this, factory, slot, stack.size))
+                    }
+                }
+            }
+            join_tasks(tasks).await
+        })
+    }
+}
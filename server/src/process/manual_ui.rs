@@ -74,6 +74,9 @@ fn make_pred(needle: &str) -> Box<dyn Fn(&DetailStack) -> bool> {
 
 impl ManualUiProcess {
     fn update_view(&self, tui: &Tui) {
+        if tui.main_list_pinned.get() {
+            return;
+        }
         let text_area = tui.text_area.borrow();
         let mut needle = text_area.lines().get(text_area.cursor().0).map(|x| x.as_str()).unwrap_or("");
         if let Some(pos) = needle.rfind('*') {
@@ -117,7 +120,11 @@ impl Process for ManualUiProcess {
                     let pred = make_pred(&request[..pos]);
                     let Some(stack) = this.latest_view.iter().find(|x| pred(x)) else { continue };
                     let Ok(mut size) = request[pos + 1..].parse() else { continue };
-                    size = factory.items.get(&stack.item).map_or(0, |info| info.borrow().n_stored).min(size);
+                    size = factory
+                        .items
+                        .get(&stack.item)
+                        .map_or(0, |info| info.borrow().get_availability(true, 0))
+                        .min(size);
                     loop {
                         let InsertPlan { n_inserted, insertions } = insert_into_inventory(
                             &mut stacks,
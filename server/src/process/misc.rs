@@ -44,6 +44,14 @@ impl<T: Process> Process for ConditionalProcess<T> {
             spawn(async { Ok(()) })
         }
     }
+
+    fn on_estop(&self, factory: &Factory) -> Option<ChildTask<Result<(), LocalStr>>> {
+        self.child.borrow().on_estop(factory)
+    }
+
+    fn has_pending_work(&self, factory: &Factory) -> bool {
+        (self.condition)(factory) && self.child.borrow().has_pending_work(factory)
+    }
 }
 
 pub struct SyncAndRestockConfig {
@@ -220,12 +228,7 @@ pub struct LowAlert {
 
 impl LowAlert {
     pub fn new(item: Filter, n_wanted: i32) -> Self {
-        let log = match &item {
-            Filter::Label(x) => x.clone(),
-            Filter::Name(x) => local_fmt!("<{}>", x),
-            Filter::Both { label, name } => local_fmt!("{} <{}>", label, name),
-            Filter::Custom { desc, .. } => local_fmt!("<{}>", desc),
-        };
+        let log = item.describe();
         Self { item, n_wanted, log }
     }
 }
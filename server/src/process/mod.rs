@@ -10,6 +10,9 @@ use std::{cell::RefCell, iter::once, rc::Rc};
 
 pub trait Process: 'static {
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>>;
+    fn on_estop(&self, _factory: &Factory) -> Option<ChildTask<Result<(), LocalStr>>> { None }
+    // Whether a recipe could be crafted right now, used to spot processes that never act on their demand.
+    fn has_pending_work(&self, _factory: &Factory) -> bool { false }
 }
 
 pub trait IntoProcess {
@@ -132,7 +135,9 @@ mod blocking_output;
 mod buffered;
 mod crafty;
 mod drone;
+mod export;
 mod fluid_slotted;
+mod import;
 mod manual_ui;
 mod misc;
 mod multi_inv_slotted;
@@ -146,7 +151,9 @@ pub use blocking_output::*;
 pub use buffered::*;
 pub use crafty::*;
 pub use drone::*;
+pub use export::*;
 pub use fluid_slotted::*;
+pub use import::*;
 pub use manual_ui::*;
 pub use misc::*;
 pub use multi_inv_slotted::*;
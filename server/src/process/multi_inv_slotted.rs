@@ -205,6 +205,8 @@ impl Process for MultiInvSlottedProcess {
             join_tasks(tasks).await
         })
     }
+
+    fn has_pending_work(&self, factory: &Factory) -> bool { !compute_demands(factory, &self.recipes).is_empty() }
 }
 
 impl MultiInvSlottedProcess {
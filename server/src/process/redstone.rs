@@ -1,8 +1,11 @@
-use super::super::access::RedstoneAccess;
+use super::super::access::{BasicAccess, RedstoneAccess};
 use super::super::action::{ActionFuture, Log, RedstoneInput, RedstoneOutput};
+use super::super::detail_cache::DetailCache;
 use super::super::factory::Factory;
-use super::super::inventory::Inventory;
+use super::super::inventory::{list_inventory, Inventory};
+use super::super::item::Filter;
 use super::super::recipe::Outputs;
+use super::super::server::Server;
 use super::super::util::{alive, spawn};
 use super::{IntoProcess, Process};
 use abort_on_drop::ChildTask;
@@ -23,24 +26,99 @@ pub fn emit_when_want_item(name: LocalStr, off: u8, on: u8, outputs: Box<dyn Out
     })
 }
 
+// Emits `on` while any of `triggers` is in storage. Evaluated every run, so re-arming after an estop restores it.
+pub fn emit_when_stored(name: LocalStr, off: u8, on: u8, triggers: Vec<Filter>) -> RedstoneFn {
+    Box::new(move |factory| {
+        if triggers.iter().any(|x| factory.search_n_stored(x) > 0) {
+            factory.log(Log { text: local_fmt!("{}: on", name), color: 10 });
+            return on;
+        }
+        off
+    })
+}
+
 pub struct RedstoneEmitterConfig {
     pub accesses: Vec<RedstoneAccess>,
     pub output: RedstoneFn,
+    pub off: u8,
+}
+
+fn emit(factory: &Factory, accesses: &[RedstoneAccess], value: u8) -> ChildTask<Result<(), LocalStr>> {
+    let server = factory.get_server().borrow();
+    let access = server.load_balance(accesses);
+    let action = ActionFuture::from(RedstoneOutput {
+        side: access.side.clone(),
+        addr: access.addr.clone(),
+        bit: access.bit,
+        value,
+    });
+    server.enqueue_request_group(&access.client, vec![action.clone().into()]);
+    spawn(async move { action.await.map(|_| ()) })
 }
 
 impl Process for RedstoneEmitterConfig {
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
-        let value = (self.output)(factory);
-        let server = factory.get_server().borrow();
-        let access = server.load_balance(&self.accesses);
-        let action = ActionFuture::from(RedstoneOutput {
-            side: access.side.clone(),
-            addr: access.addr.clone(),
-            bit: access.bit,
-            value,
-        });
-        server.enqueue_request_group(&access.client, vec![action.clone().into()]);
-        spawn(async move { action.await.map(|_| ()) })
+        let value = if factory.is_estopped() { self.off } else { (self.output)(factory) };
+        emit(factory, &self.accesses, value)
+    }
+
+    fn on_estop(&self, factory: &Factory) -> Option<ChildTask<Result<(), LocalStr>>> {
+        Some(emit(factory, &self.accesses, self.off))
+    }
+}
+
+// Emits `on` while the inventory is at least `threshold` full, measured like a comparator: the average over all
+// slots of each stack's size relative to its max stack size.
+pub struct FillEmitterConfig {
+    pub name: LocalStr,
+    pub accesses: Vec<BasicAccess>,
+    pub outputs: Vec<RedstoneAccess>,
+    pub threshold: f64,
+    pub on: u8,
+    pub off: u8,
+}
+
+pub struct FillEmitterProcess {
+    weak: Weak<RefCell<FillEmitterProcess>>,
+    config: FillEmitterConfig,
+    detail_cache: Rc<RefCell<DetailCache>>,
+    factory: Weak<RefCell<Factory>>,
+    server: Rc<RefCell<Server>>,
+    size: Option<usize>,
+}
+
+impl_inventory!(FillEmitterProcess, BasicAccess);
+impl_into_process!(FillEmitterConfig, FillEmitterProcess);
+
+impl Process for FillEmitterProcess {
+    fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
+        if factory.is_estopped() {
+            return emit(factory, &self.config.outputs, self.config.off);
+        }
+        let stacks = list_inventory(self);
+        let weak = self.weak.clone();
+        spawn(async move {
+            let stacks = stacks.await?;
+            let n_slots = stacks.len().max(1) as f64;
+            let fill = stacks.iter().flatten().map(|x| x.size as f64 / x.detail.max_size as f64).sum::<f64>() / n_slots;
+            let task = {
+                alive!(weak, this);
+                upgrade!(this.factory, factory);
+                let value = if fill >= this.config.threshold {
+                    let text = local_fmt!("{}: on ({:.0}% full)", this.config.name, fill * 100.);
+                    factory.log(Log { text, color: 10 });
+                    this.config.on
+                } else {
+                    this.config.off
+                };
+                emit(factory, &this.config.outputs, value)
+            };
+            task.await.unwrap()
+        })
+    }
+
+    fn on_estop(&self, factory: &Factory) -> Option<ChildTask<Result<(), LocalStr>>> {
+        Some(emit(factory, &self.config.outputs, self.config.off))
     }
 }
 
@@ -100,4 +178,8 @@ impl<T: Process> Process for RedstoneConditionalProcess<T> {
             task.await.unwrap()
         })
     }
+
+    fn on_estop(&self, factory: &Factory) -> Option<ChildTask<Result<(), LocalStr>>> {
+        self.child.borrow().on_estop(factory)
+    }
 }
@@ -39,9 +39,10 @@ pub struct SlottedRecipe {
     pub outputs: Rc<dyn Outputs>,
     pub inputs: Vec<SlottedInput>,
     pub max_sets: i32,
+    pub priority: i32,
 }
 
-impl_recipe!(SlottedRecipe, SlottedInput);
+impl_recipe!(SlottedRecipe, SlottedInput, priority);
 
 pub struct SlottedConfig {
     pub name: LocalStr,
@@ -50,6 +51,8 @@ pub struct SlottedConfig {
     pub to_extract: Option<ExtractFilter>,
     pub recipes: Vec<SlottedRecipe>,
     pub strict_priority: bool,
+    // Caps the sets dispatched per cycle across all recipes; None is unlimited.
+    pub max_crafts_per_cycle: Option<i32>,
 }
 
 pub struct SlottedProcess {
@@ -92,49 +95,80 @@ impl Process for SlottedProcess {
                         }
                     }
                 }
-                let mut demands = compute_demands(factory, &this.config.recipes);
-                if this.config.strict_priority {
-                    demands.truncate(1)
+                this.dispatch_recipes(factory, &existing_inputs, |factory, demand| {
+                    tasks.push(this.execute_recipe(factory, demand))
+                })
+            }
+            join_tasks(tasks).await
+        })
+    }
+
+    fn has_pending_work(&self, factory: &Factory) -> bool { !compute_demands(factory, &self.config.recipes).is_empty() }
+}
+
+impl SlottedProcess {
+    // Recipes are tried by descending recipe then demand priority, ties in config order. With strict_priority the
+    // first recipe that fits is the only one dispatched; otherwise later recipes may fill the slots left over.
+    fn dispatch_recipes(
+        &self,
+        factory: &mut Factory,
+        existing_inputs: &FnvHashMap<usize, Option<DetailStack>>,
+        mut dispatch: impl FnMut(&mut Factory, Demand),
+    ) {
+        let mut dispatched = FnvHashSet::<usize>::default();
+        let mut claimed_slots = FnvHashSet::<usize>::default();
+        let mut budget = self.config.max_crafts_per_cycle.unwrap_or(i32::MAX);
+        'retry: while budget > 0 {
+            'recipe: for mut demand in compute_demands(factory, &self.config.recipes).into_iter() {
+                if dispatched.contains(&demand.i_recipe) {
+                    continue;
                 }
-                'recipe: for mut demand in demands.into_iter() {
-                    let recipe = &this.config.recipes[demand.i_recipe];
-                    let mut used_slots = FnvHashSet::<usize>::default();
-                    for (i_input, input) in recipe.inputs.iter().enumerate() {
-                        for (slot, mult) in &input.slots {
-                            let existing_input = existing_inputs.get(slot).unwrap();
-                            let existing_size = if let Some(existing_input) = existing_input {
-                                if existing_input.item != demand.inputs.items[i_input].0 {
-                                    continue 'recipe;
-                                }
-                                existing_input.size
-                            } else {
-                                0
-                            };
-                            demand.inputs.n_sets = demand.inputs.n_sets.min(
-                                ((recipe.max_sets * mult).min(demand.inputs.items[i_input].1.max_size) - existing_size)
-                                    / mult,
-                            );
-                            if demand.inputs.n_sets <= 0 {
+                let recipe = &self.config.recipes[demand.i_recipe];
+                let mut used_slots = FnvHashSet::<usize>::default();
+                for (i_input, input) in recipe.inputs.iter().enumerate() {
+                    for (slot, mult) in &input.slots {
+                        if claimed_slots.contains(slot) {
+                            continue 'recipe;
+                        }
+                        let existing_input = existing_inputs.get(slot).unwrap();
+                        let existing_size = if let Some(existing_input) = existing_input {
+                            if existing_input.item != demand.inputs.items[i_input].0 {
                                 continue 'recipe;
                             }
-                            used_slots.insert(*slot);
-                        }
-                    }
-                    for (slot, existing_input) in &existing_inputs {
-                        if existing_input.is_some() && !used_slots.contains(slot) {
+                            existing_input.size
+                        } else {
+                            0
+                        };
+                        demand.inputs.n_sets = demand.inputs.n_sets.min(
+                            ((recipe.max_sets * mult).min(demand.inputs.items[i_input].1.max_size) - existing_size)
+                                / mult,
+                        );
+                        if demand.inputs.n_sets <= 0 {
                             continue 'recipe;
                         }
+                        used_slots.insert(*slot);
                     }
-                    tasks.push(this.execute_recipe(factory, demand));
-                    break;
                 }
+                for (slot, existing_input) in existing_inputs {
+                    if existing_input.is_some() && !used_slots.contains(slot) && !claimed_slots.contains(slot) {
+                        continue 'recipe;
+                    }
+                }
+                demand.inputs.n_sets = demand.inputs.n_sets.min(budget);
+                budget -= demand.inputs.n_sets;
+                dispatched.insert(demand.i_recipe);
+                dispatch(factory, demand);
+                if self.config.strict_priority {
+                    break 'retry;
+                }
+                claimed_slots.extend(used_slots);
+                // Reservations changed what's available, so demands are recomputed.
+                continue 'retry;
             }
-            join_tasks(tasks).await
-        })
+            break;
+        }
     }
-}
 
-impl SlottedProcess {
     fn execute_recipe(&self, factory: &mut Factory, demand: Demand) -> ChildTask<Result<(), LocalStr>> {
         let mut bus_slots = Vec::new();
         let slots_to_free = Rc::new(RefCell::new(Vec::new()));
@@ -201,3 +235,56 @@ impl SlottedProcess {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::factory::tests::{stock, with_test_factory};
+    use crate::recipe::ignore_outputs;
+
+    fn recipe(slot: usize, priority: i32) -> SlottedRecipe {
+        SlottedRecipe {
+            outputs: ignore_outputs(1.),
+            inputs: vec![SlottedInput::new(Filter::Name("minecraft:iron_ingot".into()), vec![(slot, 1)])],
+            max_sets: 8,
+            priority,
+        }
+    }
+
+    // Returns the recipes dispatched in one cycle, in order, with both input slots empty and plenty in stock.
+    fn dispatched(strict_priority: bool, recipes: Vec<SlottedRecipe>) -> Vec<usize> {
+        with_test_factory(|factory, _| async move {
+            let mut factory = factory.borrow_mut();
+            stock(&mut factory, "minecraft:iron_ingot", 64);
+            let config = SlottedConfig {
+                name: "press".into(),
+                accesses: Vec::new(),
+                input_slots: vec![0, 1],
+                to_extract: None,
+                recipes,
+                strict_priority,
+                max_crafts_per_cycle: None,
+            };
+            let process = config.into_process(&factory);
+            let existing_inputs = [(0, None), (1, None)].into_iter().collect();
+            let mut result = Vec::new();
+            process.borrow().dispatch_recipes(&mut factory, &existing_inputs, |_, demand| result.push(demand.i_recipe));
+            result
+        })
+    }
+
+    #[test]
+    fn strict_priority_stops_after_first_dispatch() {
+        assert_eq!(dispatched(true, vec![recipe(0, 0), recipe(1, 1)]), [1]);
+        assert_eq!(dispatched(false, vec![recipe(0, 0), recipe(1, 1)]), [1, 0]);
+    }
+
+    #[test]
+    fn recipes_contending_for_a_slot_dispatch_only_the_first() {
+        for strict_priority in [false, true] {
+            assert_eq!(dispatched(strict_priority, vec![recipe(0, 0), recipe(0, 1)]), [1]);
+            // Equal priorities and demands keep config order.
+            assert_eq!(dispatched(strict_priority, vec![recipe(0, 0), recipe(0, 0)]), [0]);
+        }
+    }
+}
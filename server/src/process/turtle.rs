@@ -6,12 +6,21 @@ use super::super::lua_value::{call_result, Value};
 use super::super::util::{alive, make_local_one_shot, spawn};
 use super::{IntoProcess, Process};
 use abort_on_drop::ChildTask;
-use flexstr::{local_fmt, LocalStr};
+use flexstr::{local_fmt, local_str, LocalStr};
 use futures_util::future::pending;
 use serde::{Deserialize, Serialize};
 use std::io::{BufReader, BufWriter};
 use std::rc::{Rc, Weak};
-use std::{cell::RefCell, fs::File, future::Future, marker::PhantomData};
+use std::time::Duration;
+use std::{
+    cell::{Cell, RefCell},
+    fs::File,
+    future::Future,
+    marker::PhantomData,
+};
+use tokio::time::Instant;
+
+const FUEL_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
 pub struct TurtleContext<State: Serialize> {
     _phantom: PhantomData<dyn Fn(State) -> ()>,
@@ -101,6 +110,7 @@ pub struct TurtleConfig<State: Serialize + for<'a> Deserialize<'a>, Task: Future
     pub name: LocalStr,
     pub file_name: LocalStr,
     pub client: LocalStr,
+    pub low_fuel: i32,
     pub program: Box<dyn FnOnce(TurtleContext<State>, Option<State>) -> Task>,
 }
 
@@ -109,6 +119,8 @@ pub struct TurtleProcess {
     factory: Weak<RefCell<Factory>>,
     name: LocalStr,
     client: LocalStr,
+    low_fuel: i32,
+    last_fuel_check: Cell<Option<Instant>>,
     sync_queue: Vec<Box<dyn FnOnce(&Factory)>>,
     _task: ChildTask<()>,
 }
@@ -127,6 +139,8 @@ impl<State: Serialize + for<'a> Deserialize<'a>, Task: Future<Output = ()> + 'st
                 factory: factory.get_weak().clone(),
                 name: self.name,
                 client: self.client,
+                low_fuel: self.low_fuel,
+                last_fuel_check: Cell::new(None),
                 sync_queue: Vec::new(),
                 _task: spawn((self.program)(context, state)),
             })
@@ -135,13 +149,32 @@ impl<State: Serialize + for<'a> Deserialize<'a>, Task: Future<Output = ()> + 'st
 }
 
 impl Process for TurtleProcess {
-    fn run(&self, _: &Factory) -> ChildTask<Result<(), LocalStr>> {
+    fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
+        let now = Instant::now();
+        let fuel = if self.last_fuel_check.get().map_or(true, |last| now - last >= FUEL_CHECK_INTERVAL) {
+            self.last_fuel_check.set(Some(now));
+            let server = factory.get_server().borrow();
+            let action = ActionFuture::from(TurtleCall { func: local_str!("getFuelLevel"), args: Vec::new() });
+            server.enqueue_request_group(&self.client, vec![action.clone().into()]);
+            Some(action)
+        } else {
+            None
+        };
         let weak = self.weak.clone();
         spawn(async move {
-            alive_mut!(weak, this);
-            upgrade!(this.factory, factory);
-            for sync_task in std::mem::take(&mut this.sync_queue) {
-                sync_task(factory)
+            {
+                alive_mut!(weak, this);
+                upgrade!(this.factory, factory);
+                for sync_task in std::mem::take(&mut this.sync_queue) {
+                    sync_task(factory)
+                }
+            }
+            let Some(fuel) = fuel else { return Ok(()) };
+            // Turtles with fuel disabled report "unlimited", which doesn't parse.
+            if let Ok(Ok(level)) = fuel.await.map(call_result::<i32>) {
+                alive!(weak, this);
+                upgrade_mut!(this.factory, factory);
+                factory.update_turtle_fuel(&this.name, level, this.low_fuel)
             }
             Ok(())
         })
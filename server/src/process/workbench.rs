@@ -18,6 +18,8 @@ pub struct WorkbenchConfig {
     pub name: LocalStr,
     pub accesses: Vec<BusAccess>,
     pub recipes: Vec<CraftingGridRecipe>,
+    // Caps the sets dispatched per cycle across all recipes; None is unlimited.
+    pub max_crafts_per_cycle: Option<i32>,
 }
 
 pub struct WorkbenchProcess {
@@ -35,13 +37,18 @@ impl IntoProcess for WorkbenchConfig {
 impl Process for WorkbenchProcess {
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         let mut tasks = Vec::new();
+        let mut budget = self.config.max_crafts_per_cycle.unwrap_or(i32::MAX);
         for Demand { i_recipe, .. } in compute_demands(factory, &self.config.recipes) {
+            if budget <= 0 {
+                break;
+            }
             let recipe = &self.config.recipes[i_recipe];
             if recipe.max_sets <= 0 {
                 continue;
             }
             if let Some(ResolvedInputs { mut n_sets, items, .. }) = resolve_inputs(factory, recipe) {
-                n_sets = n_sets.min(recipe.max_sets);
+                n_sets = n_sets.min(recipe.max_sets).min(budget);
+                budget -= n_sets;
                 let mut bus_slots = Vec::new();
                 let slots_to_free = Rc::new(RefCell::new(Vec::new()));
                 for (i_input, (item, _)) in items.into_iter().enumerate() {
@@ -115,6 +122,8 @@ impl Process for WorkbenchProcess {
         }
         spawn(async move { join_tasks(tasks).await })
     }
+
+    fn has_pending_work(&self, factory: &Factory) -> bool { !compute_demands(factory, &self.config.recipes).is_empty() }
 }
 
 fn load_input(group: &mut Vec<Call>, access: &BusAccess, bus_slot: usize, inv_slot: usize, size: i32) {
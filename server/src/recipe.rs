@@ -3,7 +3,7 @@ use super::item::{Detail, Filter, Item};
 use flexstr::LocalStr;
 use fnv::FnvHashMap;
 use std::{
-    cmp::{max_by, min_by},
+    cmp::{max, max_by, min_by},
     collections::hash_map::Entry,
     rc::Rc,
 };
@@ -61,10 +61,31 @@ impl Output {
 
 impl Outputs for Output {
     fn get_priority(&self, factory: &Factory) -> Option<f64> {
+        let n_wanted = max(self.n_wanted, factory.get_craft_target(&self.item));
         let n_stored = factory.search_n_stored(&self.item);
-        let n_needed = self.n_wanted - n_stored;
+        let n_needed = n_wanted - n_stored;
         if n_needed > 0 {
-            Some(n_needed as f64 / self.n_wanted as f64)
+            Some(n_needed as f64 / n_wanted as f64)
+        } else {
+            None
+        }
+    }
+}
+
+// Never asks for a craft itself, but vetoes one once enough of the byproduct is stored.
+pub struct Byproduct {
+    pub item: Filter,
+    pub n_max: i32,
+}
+
+impl Byproduct {
+    pub fn new(item: Filter, n_max: i32) -> Rc<dyn Outputs> { Rc::new(Self { item, n_max }) }
+}
+
+impl Outputs for Byproduct {
+    fn get_priority(&self, factory: &Factory) -> Option<f64> {
+        if factory.search_n_stored(&self.item) < self.n_max {
+            Some(1.)
         } else {
             None
         }
@@ -126,6 +147,8 @@ pub trait Recipe: Clone {
     type In: Input;
     fn get_outputs(&self) -> &dyn Outputs;
     fn get_inputs(&self) -> &Vec<Self::In>;
+    // Recipes with a higher priority are tried before any with a lower one, whatever their demand.
+    fn get_priority(&self) -> i32 { 0 }
 }
 
 macro_rules! impl_recipe {
@@ -136,6 +159,20 @@ macro_rules! impl_recipe {
             fn get_inputs(&self) -> &Vec<$i> { &self.inputs }
         }
     };
+    ($r:ident, $i:ident, priority) => {
+        impl Recipe for $r {
+            type In = $i;
+            fn get_outputs(&self) -> &dyn Outputs { &*self.outputs }
+            fn get_inputs(&self) -> &Vec<$i> { &self.inputs }
+            fn get_priority(&self) -> i32 { self.priority }
+        }
+    };
+}
+
+pub struct RecipeBookEntry {
+    pub process: LocalStr,
+    pub outputs: Vec<Filter>,
+    pub inputs: Vec<(Filter, i32)>,
 }
 
 pub struct ResolvedInputs {
@@ -200,7 +237,12 @@ pub fn compute_demands(factory: &Factory, recipes: &[impl Recipe]) -> Vec<Demand
         priority *= inputs.priority as f64;
         result.push(Demand { i_recipe, inputs, priority })
     }
-    result.sort_by(|x: &Demand, y: &Demand| x.priority.partial_cmp(&y.priority).unwrap().reverse());
+    // Stable, so recipes tied on both keep their config order.
+    result.sort_by(|x: &Demand, y: &Demand| {
+        let (x_recipe, y_recipe) = (&recipes[x.i_recipe], &recipes[y.i_recipe]);
+        (y_recipe.get_priority().cmp(&x_recipe.get_priority()))
+            .then_with(|| x.priority.partial_cmp(&y.priority).unwrap().reverse())
+    });
     result
 }
 
@@ -238,6 +280,40 @@ pub struct CraftingGridRecipe {
     // can't craft more than one stack at a time.
     pub max_sets: i32,
     pub non_consumables: Vec<NonConsumable>,
+    pub priority: i32,
 }
 
-impl_recipe!(CraftingGridRecipe, CraftingGridInput);
+impl_recipe!(CraftingGridRecipe, CraftingGridInput, priority);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::factory::tests::{stock, with_test_factory};
+
+    // Both recipes take the one ingot in stock; `urgency` is the share of their output still wanted.
+    fn recipe(urgency: f64, priority: i32) -> CraftingGridRecipe {
+        CraftingGridRecipe {
+            outputs: ignore_outputs(urgency),
+            inputs: vec![CraftingGridInput::new(Filter::Name("minecraft:iron_ingot".into()), vec![0])],
+            max_sets: 1,
+            non_consumables: Vec::new(),
+            priority,
+        }
+    }
+
+    fn order(recipes: &[CraftingGridRecipe]) -> Vec<usize> {
+        with_test_factory(|factory, _| async move {
+            stock(&mut factory.borrow_mut(), "minecraft:iron_ingot", 1);
+            let factory = factory.borrow();
+            compute_demands(&factory, recipes).into_iter().map(|x| x.i_recipe).collect()
+        })
+    }
+
+    #[test]
+    fn higher_priority_recipe_takes_contested_input_first() {
+        assert_eq!(order(&[recipe(1., 0), recipe(0.5, 0)]), [0, 1]);
+        assert_eq!(order(&[recipe(1., 0), recipe(0.5, 1)]), [1, 0]);
+        // Ties on both keep config order.
+        assert_eq!(order(&[recipe(1., 0), recipe(1., 0)]), [0, 1]);
+    }
+}
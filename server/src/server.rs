@@ -1,8 +1,11 @@
-use crate::lua_value::{serialize, table_remove, vec_to_table, Parser, Table, Value};
-use crate::{access::GetClient, action::ActionRequest, util::spawn, Tui};
+use crate::action::{
+    ActionFuture, ActionRequest, Ping, ProtocolVersion, RetryPolicy, FRAMED_VERSION, PROTOCOL_VERSION,
+};
+use crate::lua_value::{serialize, table_remove, try_into_integer, vec_to_table, Parser, Table, Value};
+use crate::{access::GetClient, util::spawn, Tui};
 use abort_on_drop::ChildTask;
-use flexstr::{local_fmt, LocalStr};
-use fnv::FnvHashMap;
+use flexstr::{local_fmt, local_str, LocalStr};
+use fnv::{FnvHashMap, FnvHashSet};
 use futures_util::{
     sink::SinkExt,
     stream::{SplitSink, SplitStream, StreamExt},
@@ -12,9 +15,11 @@ use std::{
     cell::RefCell,
     collections::VecDeque,
     fmt::Write,
-    mem::replace,
+    io,
+    mem::{replace, take},
     net::{Ipv6Addr, SocketAddr},
     rc::{Rc, Weak},
+    str::FromStr,
     time::Duration,
 };
 use tokio::{
@@ -23,11 +28,36 @@ use tokio::{
 };
 use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
 
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_ACTION_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 1024;
+pub const DEFAULT_RECONNECT_GRACE: Duration = Duration::from_secs(10);
+
 pub struct Server {
+    weak: Weak<RefCell<Server>>,
     pub tui: Rc<Tui>,
+    pub auth_token: Option<LocalStr>,
+    pub timeout: Duration,
+    // A request unanswered for this long is failed on its own instead of holding up the cycle; zero disables.
+    pub action_timeout: Duration,
+    pub strict_version: bool,
+    pub max_in_flight: usize,
+    pub retry: RetryPolicy,
+    pub dry_run: bool,
+    // How long requests for a dropped client are held in case it logs back in; zero fails them right away.
+    pub reconnect_grace: Duration,
     clients: Option<Rc<RefCell<Client>>>,
     logins: FnvHashMap<LocalStr, Weak<RefCell<Client>>>,
-    _acceptor: ChildTask<()>,
+    parked: RefCell<FnvHashMap<LocalStr, Parked>>,
+    // Set by listen; a reload keeps the server, and its clients, when the port stays the same.
+    port: Option<u16>,
+    _acceptor: Option<ChildTask<()>>,
+}
+
+// Requests that were still queued when their client dropped, plus any sent its way since.
+struct Parked {
+    request_queue: Vec<Vec<Rc<RefCell<dyn ActionRequest>>>>,
+    _expiry: ChildTask<()>,
 }
 
 impl Drop for Server {
@@ -52,13 +82,23 @@ struct Client {
     prev: Option<Weak<RefCell<Client>>>,
     server: Weak<RefCell<Server>>,
     login: Option<LocalStr>,
+    authed: bool,
+    version: Option<i32>,
     _reader: ChildTask<()>,
     request_queue: VecDeque<Vec<Rc<RefCell<dyn ActionRequest>>>>,
     request_queue_size: usize,
     next_request_id: usize,
     response_queue: FnvHashMap<usize, Rc<RefCell<dyn ActionRequest>>>,
+    action_timeouts: FnvHashMap<usize, ChildTask<()>>,
+    // Requests given up on whose responses may still arrive late.
+    timed_out: FnvHashSet<usize>,
     writer: WriterState,
     timeout: Option<ChildTask<()>>,
+    timeout_duration: Duration,
+    action_timeout: Duration,
+    max_in_flight: usize,
+    throttled: bool,
+    _heartbeat: Option<ChildTask<()>>,
 }
 
 impl Drop for Client {
@@ -77,6 +117,11 @@ impl Client {
     fn disconnect_by_server(&mut self, server: &mut Server) {
         if let Some(login) = &self.login {
             server.logins.remove(login);
+            // Requests already sent may have run, so only the unsent ones are safe to replay.
+            if !server.reconnect_grace.is_zero() {
+                self.request_queue_size = 0;
+                server.park(login.clone(), take(&mut self.request_queue).into())
+            }
         }
         if let Some(next) = self.next.as_ref() {
             next.borrow_mut().prev = self.prev.clone()
@@ -96,6 +141,10 @@ impl Client {
     fn enqueue_request_group(&mut self, group: Vec<Rc<RefCell<dyn ActionRequest>>>) {
         self.request_queue_size += group.len();
         self.request_queue.push_back(group);
+        self.resume_writer()
+    }
+
+    fn resume_writer(&mut self) {
         let writer = replace(&mut self.writer, WriterState::Invalid);
         if let WriterState::NotWriting(stream) = writer {
             self.update_timeout(false);
@@ -109,18 +158,94 @@ impl Client {
         if self.request_queue_size == 0 && self.response_queue.is_empty() {
             self.timeout = None
         } else if restart || self.timeout.is_none() {
-            self.timeout = Some(spawn(timeout_main(self.weak.clone())))
+            self.timeout = Some(spawn(timeout_main(self.weak.clone(), self.timeout_duration)))
         }
     }
 
     fn estimate_load(&self) -> usize { self.request_queue_size + self.response_queue.len() }
 }
 
-async fn timeout_main(client: Weak<RefCell<Client>>) {
-    sleep(Duration::from_secs(30)).await;
+async fn timeout_main(client: Weak<RefCell<Client>>, timeout: Duration) {
+    sleep(timeout).await;
     if let Some(this) = client.upgrade() {
-        this.borrow_mut().log_and_disconnect(format_args!("request timeout"))
+        let mut this = this.borrow_mut();
+        this.tui.log(format!("{}: request timeout", this.log_prefix), 6);
+        this.disconnect()
+    }
+}
+
+async fn expiry_main(server: Weak<RefCell<Server>>, client: LocalStr, grace: Duration) {
+    sleep(grace).await;
+    let Some(this) = server.upgrade() else { return };
+    let Some(parked) = this.borrow().parked.borrow_mut().remove(&client) else { return };
+    let n_requests: usize = parked.request_queue.iter().map(|x| x.len()).sum();
+    this.borrow().tui.log(format!("{client}: didn't reconnect in time, failing {n_requests} requests"), 6);
+    let reason = local_fmt!("{} isn't connected", client);
+    for x in parked.request_queue.into_iter().flatten() {
+        x.borrow_mut().on_fail(reason.clone())
+    }
+}
+
+async fn action_timeout_main(client: Weak<RefCell<Client>>, id: usize, desc: String, timeout: Duration) {
+    sleep(timeout).await;
+    let Some(this) = client.upgrade() else { return };
+    let mut this = this.borrow_mut();
+    let Some(request) = this.response_queue.remove(&id) else { return };
+    this.tui.log(format!("{}: {desc} timed out", this.log_prefix), 6);
+    this.timed_out.insert(id);
+    if this.response_queue.is_empty() {
+        this.throttled = false
+    }
+    if !this.request_queue.is_empty() {
+        this.resume_writer()
     }
+    this.update_timeout(false);
+    this.action_timeouts.remove(&id);
+    drop(this);
+    request.borrow_mut().on_fail(local_fmt!("{desc} timed out"))
+}
+
+async fn heartbeat_main(client: Weak<RefCell<Client>>, interval: Duration) {
+    loop {
+        sleep(interval).await;
+        let Some(this) = client.upgrade() else { break };
+        let mut this = this.borrow_mut();
+        // Busy clients already prove they're alive through their responses.
+        if this.estimate_load() == 0 {
+            this.enqueue_request_group(vec![ActionFuture::from(Ping).into()])
+        }
+    }
+}
+
+// Names the peripheral method a request calls, for logs about it.
+fn describe_call(table: &Table) -> String {
+    let func = match table.get(&"v".into()) {
+        Some(Value::T(args)) => args.get(&1.into()),
+        _ => None,
+    };
+    match (table.get(&"p".into()), func) {
+        (Some(Value::S(addr)), Some(Value::S(func))) => format!("{}.{}", addr, func),
+        (Some(Value::S(addr)), _) => addr.to_string(),
+        _ => "request".to_owned(),
+    }
+}
+
+// Describes an item transfer and returns the count it would move, so a dry run can pretend it fully succeeded.
+fn describe_transfer(table: &Table) -> Option<(String, Value)> {
+    let (Some(Value::S(addr)), Some(Value::T(args))) = (table.get(&"p".into()), table.get(&"v".into())) else {
+        return None;
+    };
+    let Some(Value::S(func)) = args.get(&1.into()) else { return None };
+    if !matches!(&**func, "pushItems" | "pullItems") {
+        return None;
+    }
+    let arg = |i: usize| match args.get(&i.into()) {
+        Some(Value::S(x)) => x.to_string(),
+        Some(Value::F(x)) => x.to_string(),
+        _ => "nil".to_owned(),
+    };
+    let text = format!("{}.{}({}, {}, {}, {})", addr, func, arg(2), arg(3), arg(4), arg(5));
+    Some((text, args.get(&4.into())?.clone()))
 }
 
 async fn writer_main(client: Weak<RefCell<Client>>, mut sink: SplitSink<WebSocketStream<TcpStream>, Message>) {
@@ -129,17 +254,49 @@ async fn writer_main(client: Weak<RefCell<Client>>, mut sink: SplitSink<WebSocke
         {
             let Some(this) = client.upgrade() else { break };
             let mut this = this.borrow_mut();
-            let Some(group) = this.request_queue.pop_front() else { break this.writer = WriterState::NotWriting(sink) };
-            this.request_queue_size -= group.len();
+            let dry_run = this.server.upgrade().is_some_and(|x| x.borrow().dry_run);
             let mut value = Vec::new();
-            for request in group {
-                let id = this.next_request_id;
-                this.next_request_id += 1;
-                let mut table = Table::new();
-                table.insert("i".into(), id.into());
-                request.borrow_mut().build_request(&mut table);
-                value.push(table.into());
-                this.response_queue.insert(id, request);
+            // Everything queued goes out in one message, so a cycle's list calls share a round-trip.
+            while let Some(group_size) = this.request_queue.front().map(|x| x.len()) {
+                // An oversized group still goes out once nothing else is in flight.
+                if !this.response_queue.is_empty() && this.response_queue.len() + group_size > this.max_in_flight {
+                    if !this.throttled {
+                        this.throttled = true;
+                        let text = format!(
+                            "{}: {} actions in flight, holding back",
+                            this.log_prefix,
+                            this.response_queue.len()
+                        );
+                        this.tui.log(text, 1)
+                    }
+                    break;
+                }
+                let group = this.request_queue.pop_front().unwrap();
+                this.request_queue_size -= group.len();
+                for request in group {
+                    let id = this.next_request_id;
+                    this.next_request_id += 1;
+                    let mut table = Table::new();
+                    table.insert("i".into(), id.into());
+                    request.borrow_mut().build_request(&mut table);
+                    if dry_run {
+                        if let Some((text, n)) = describe_transfer(&table) {
+                            this.tui.log(format!("{}: dry run: {}", this.log_prefix, text), 8);
+                            let _ = request.borrow_mut().on_response(vec_to_table(vec![n]).into());
+                            continue;
+                        }
+                    }
+                    if !this.action_timeout.is_zero() {
+                        let desc = describe_call(&table);
+                        let timeout = spawn(action_timeout_main(client.clone(), id, desc, this.action_timeout));
+                        this.action_timeouts.insert(id, timeout);
+                    }
+                    value.push(table.into());
+                    this.response_queue.insert(id, request);
+                }
+            }
+            if value.is_empty() {
+                break this.writer = WriterState::NotWriting(sink);
             }
             serialize(&vec_to_table(value).into(), &mut data);
             #[cfg(feature = "dump_traffic")]
@@ -167,19 +324,50 @@ fn on_packet(client: &Rc<RefCell<Client>>, value: Value) -> Result<(), LocalStr>
         if !table.is_empty() {
             Err(local_fmt!("garbage in packet: {:?}", table))
         } else if let Some(request) = this.response_queue.remove(&id) {
+            this.action_timeouts.remove(&id);
             this.update_timeout(true);
+            if this.response_queue.is_empty() {
+                this.throttled = false
+            }
+            if !this.request_queue.is_empty() {
+                this.resume_writer()
+            }
             match response {
                 Ok(x) => request.borrow_mut().on_response(x),
-                Err(e) => Ok(request.borrow_mut().on_fail(e)),
+                Err(e) => Ok(request.borrow_mut().on_error(e)),
             }
+        } else if this.timed_out.remove(&id) {
+            Ok(())
         } else {
             Err(local_fmt!("unexpected response: {:?}", response))
         }
+    } else if !this.authed {
+        upgrade!(this.server, server);
+        if matches!((&value, &server.auth_token), (Value::S(x), Some(token)) if x == token) {
+            this.authed = true;
+            Ok(())
+        } else {
+            this.tui.log(format!("{}: rejected: invalid auth token", this.log_prefix), 6);
+            Err(local_str!("unauthenticated"))
+        }
+    } else if let (Value::F(version), None) = (&value, this.version) {
+        this.version = Some(try_into_integer(version.into_inner())?);
+        Ok(())
     } else if let Value::S(login) = value {
         upgrade_mut!(this.server, server);
         write!(this.log_prefix, "[{}]", login).unwrap();
+        // Clients predating version negotiation don't report one.
+        let version = *this.version.get_or_insert(0);
+        if version != PROTOCOL_VERSION {
+            this.tui.log(format!("{}: protocol version {version}, expected {PROTOCOL_VERSION}", this.log_prefix), 1);
+            if server.strict_version {
+                return Err(local_str!("protocol version mismatch"));
+            }
+        }
         this.log(format_args!("logged in"));
         this.login = Some(login.clone());
+        this.enqueue_request_group(vec![ActionFuture::from(ProtocolVersion).into()]);
+        this._heartbeat = Some(spawn(heartbeat_main(Rc::downgrade(client), this.timeout_duration / 2)));
         drop(this);
         server.login(login, Rc::downgrade(client));
         Ok(())
@@ -188,8 +376,47 @@ fn on_packet(client: &Rc<RefCell<Client>>, value: Value) -> Result<(), LocalStr>
     }
 }
 
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 }
+        }
+    }
+    !crc
+}
+
+fn parse_frame_field<T: FromStr>(field: &[u8]) -> Result<T, LocalStr> {
+    std::str::from_utf8(field).ok().and_then(|x| x.parse().ok()).ok_or_else(|| local_str!("invalid frame header"))
+}
+
+// Takes one `$<len>:<crc32>:<payload>` frame off the buffer, with whether its checksum matched.
+// A malformed header loses track of frame boundaries, so that is an error rather than a dropped frame.
+fn take_frame(buf: &mut Vec<u8>) -> Result<Option<(bool, Vec<u8>)>, LocalStr> {
+    let Some(&marker) = buf.first() else { return Ok(None) };
+    if marker != b'$' {
+        return Err(local_str!("missing frame marker"));
+    }
+    let mut fields = buf[1..].splitn(3, |&x| x == b':');
+    let (Some(len), Some(crc), Some(rest)) = (fields.next(), fields.next(), fields.next()) else {
+        return if buf.len() > 32 { Err(local_str!("invalid frame header")) } else { Ok(None) };
+    };
+    let len: usize = parse_frame_field(len)?;
+    // Older CC versions hand back the checksum as a signed 32-bit number.
+    let crc = parse_frame_field::<i64>(crc)? as u32;
+    if rest.len() < len {
+        return Ok(None);
+    }
+    let header_len = buf.len() - rest.len();
+    let payload = buf[header_len..header_len + len].to_vec();
+    buf.drain(..header_len + len);
+    Ok(Some((crc32(&payload) == crc, payload)))
+}
+
 async fn reader_main(client: Weak<RefCell<Client>>, mut stream: SplitStream<WebSocketStream<TcpStream>>) {
     let mut parser = Parser::new();
+    let mut frames = Vec::new();
     loop {
         let data = stream.next().await;
         let Some(this) = client.upgrade() else { break };
@@ -198,7 +425,33 @@ async fn reader_main(client: Weak<RefCell<Client>>, mut stream: SplitStream<WebS
             Some(Ok(Message::Binary(data))) => {
                 #[cfg(feature = "dump_traffic")]
                 this.borrow().log(format_args!("in: {}", data.iter().map(|x| char::from(*x)).collect::<String>()));
-                if let Err(e) = parser.shift(&data, &mut |x| on_packet(&this, x)) {
+                let framed = {
+                    let this = this.borrow();
+                    this.login.is_some() && this.version >= Some(FRAMED_VERSION)
+                };
+                let result = if framed {
+                    frames.extend_from_slice(&data);
+                    loop {
+                        match take_frame(&mut frames) {
+                            Ok(None) => break Ok(()),
+                            Ok(Some((true, payload))) => {
+                                if let Err(e) = parser.shift(&payload, &mut |x| on_packet(&this, x)) {
+                                    break Err(e);
+                                }
+                            }
+                            Ok(Some((false, payload))) => {
+                                let this = this.borrow();
+                                let text =
+                                    format!("{}: dropped corrupt frame of {} bytes", this.log_prefix, payload.len());
+                                this.tui.log(text, 6)
+                            }
+                            Err(e) => break Err(e),
+                        }
+                    }
+                } else {
+                    parser.shift(&data, &mut |x| on_packet(&this, x))
+                };
+                if let Err(e) = result {
                     break this.borrow_mut().log_and_disconnect(format_args!("error decoding packet: {}", e));
                 }
             }
@@ -224,14 +477,14 @@ async fn handshake_main(client: Weak<RefCell<Client>>, stream: TcpStream) {
     }
 }
 
-fn create_listener(port: u16) -> TcpListener {
-    let socket = Socket::new(Domain::IPV6, Type::STREAM, None).unwrap();
-    socket.set_reuse_address(true).unwrap();
-    socket.set_only_v6(false).unwrap();
-    socket.bind(&SockAddr::from(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)))).unwrap();
-    socket.set_nonblocking(true).unwrap();
-    socket.listen(128).unwrap();
-    TcpListener::from_std(socket.into()).unwrap()
+pub fn create_listener(port: u16) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    socket.set_only_v6(false)?;
+    socket.bind(&SockAddr::from(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))))?;
+    socket.set_nonblocking(true)?;
+    socket.listen(128)?;
+    TcpListener::from_std(socket.into())
 }
 
 async fn acceptor_main(server: Weak<RefCell<Server>>, listener: TcpListener) {
@@ -248,13 +501,22 @@ async fn acceptor_main(server: Weak<RefCell<Server>>, listener: TcpListener) {
                 prev: None,
                 server: server.clone(),
                 login: None,
+                authed: this.auth_token.is_none(),
+                version: None,
                 _reader: spawn(handshake_main(weak.clone(), stream)),
                 request_queue: VecDeque::new(),
                 request_queue_size: 0,
                 next_request_id: 0,
                 response_queue: FnvHashMap::default(),
+                action_timeouts: FnvHashMap::default(),
+                timed_out: FnvHashSet::default(),
                 writer: WriterState::Invalid,
                 timeout: None,
+                timeout_duration: this.timeout,
+                action_timeout: this.action_timeout,
+                max_in_flight: this.max_in_flight,
+                throttled: false,
+                _heartbeat: None,
             };
             if let Some(ref next) = client.next {
                 next.borrow_mut().prev = Some(weak.clone())
@@ -266,29 +528,66 @@ async fn acceptor_main(server: Weak<RefCell<Server>>, listener: TcpListener) {
 }
 
 impl Server {
-    pub fn new(tui: Rc<Tui>, port: u16) -> Rc<RefCell<Self>> {
+    // Doesn't accept any clients until listen is called.
+    pub fn new(tui: Rc<Tui>) -> Rc<RefCell<Self>> {
         Rc::new_cyclic(|weak| {
             RefCell::new(Server {
+                weak: weak.clone(),
                 tui,
+                auth_token: None,
+                timeout: DEFAULT_TIMEOUT,
+                action_timeout: DEFAULT_ACTION_TIMEOUT,
+                strict_version: false,
+                max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+                retry: RetryPolicy::default(),
+                dry_run: false,
+                reconnect_grace: DEFAULT_RECONNECT_GRACE,
                 clients: None,
                 logins: FnvHashMap::default(),
-                _acceptor: spawn(acceptor_main(weak.clone(), create_listener(port))),
+                parked: RefCell::default(),
+                port: None,
+                _acceptor: None,
             })
         })
     }
 
+    pub fn listen(&mut self, port: u16) -> io::Result<()> {
+        let listener = create_listener(port)?;
+        self.port = Some(port);
+        self._acceptor = Some(spawn(acceptor_main(self.weak.clone(), listener)));
+        Ok(())
+    }
+
+    pub fn port(&self) -> Option<u16> { self.port }
+
     fn login(&mut self, name: LocalStr, client: Weak<RefCell<Client>>) {
-        if let Some(old) = self.logins.insert(name, client) {
+        let parked = self.parked.get_mut().remove(&name);
+        if let Some(old) = self.logins.insert(name, client.clone()) {
             upgrade_mut!(old, old);
             old.log(format_args!("logged in from another address"));
             old.login = None;
             old.disconnect_by_server(self)
         }
+        // Dropping the parked entry also cancels its expiry.
+        if let Some(parked) = parked.filter(|x| !x.request_queue.is_empty()) {
+            upgrade_mut!(client, client);
+            client.log(format_args!("replaying {} request groups held since it dropped", parked.request_queue.len()));
+            for group in parked.request_queue {
+                client.enqueue_request_group(group)
+            }
+        }
+    }
+
+    fn park(&mut self, client: LocalStr, request_queue: Vec<Vec<Rc<RefCell<dyn ActionRequest>>>>) {
+        let expiry = spawn(expiry_main(self.weak.clone(), client.clone(), self.reconnect_grace));
+        self.parked.get_mut().insert(client, Parked { request_queue, _expiry: expiry });
     }
 
     pub fn enqueue_request_group(&self, client: &str, group: Vec<Rc<RefCell<dyn ActionRequest>>>) {
         if let Some(client) = self.logins.get(client) {
             client.upgrade().unwrap().borrow_mut().enqueue_request_group(group)
+        } else if let Some(parked) = self.parked.borrow_mut().get_mut(client) {
+            parked.request_queue.push(group)
         } else {
             let reason = local_fmt!("{} isn't connected", client);
             for x in group {
@@ -297,6 +596,10 @@ impl Server {
         }
     }
 
+    pub fn n_clients(&self) -> usize { self.logins.len() }
+    pub fn clients(&self) -> impl Iterator<Item = &LocalStr> { self.logins.keys() }
+    pub fn is_connected(&self, client: &str) -> bool { self.logins.contains_key(client) }
+
     fn estimate_load(&self, client: &str) -> usize {
         if let Some(client) = self.logins.get(client) {
             client.upgrade().unwrap().borrow().estimate_load()
@@ -0,0 +1,33 @@
+use flexstr::{local_fmt, LocalStr};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+pub struct StatsD {
+    socket: UdpSocket,
+}
+
+impl StatsD {
+    pub fn new(addr: &str) -> Result<Self, LocalStr> {
+        let addr = (addr.to_socket_addrs().map_err(|e| local_fmt!("{addr}: {e}"))?.next())
+            .ok_or_else(|| local_fmt!("{addr}: no address"))?;
+        let local = match addr {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(local).map_err(|e| local_fmt!("{e}"))?;
+        socket.connect(addr).map_err(|e| local_fmt!("{addr}: {e}"))?;
+        socket.set_nonblocking(true).map_err(|e| local_fmt!("{e}"))?;
+        Ok(Self { socket })
+    }
+
+    fn send(&self, name: &str, value: impl std::fmt::Display, kind: &str) {
+        // Metrics are best-effort; a dropped packet shouldn't disturb the factory.
+        let _ = self.socket.send(format!("ccremote.{name}:{value}|{kind}").as_bytes());
+    }
+
+    pub fn timing(&self, name: &str, value: Duration) { self.send(name, value.as_millis(), "ms") }
+    pub fn gauge(&self, name: &str, value: i64) { self.send(name, value, "g") }
+    pub fn count(&self, name: &str, value: i64) { self.send(name, value, "c") }
+}
@@ -3,10 +3,13 @@ use super::super::action::{ActionFuture, Call};
 use super::super::detail_cache::DetailCache;
 use super::super::factory::Factory;
 use super::super::inventory::{list_inventory, Inventory};
-use super::super::item::{Detail, DetailStack, Item};
+use super::super::item::{Detail, DetailStack, Filter, Item};
+use super::super::lua_value::TransferResult;
 use super::super::server::Server;
 use super::super::util::{alive, spawn};
-use super::{DepositResult, Extractor, IntoStorage, Provider, Storage};
+use super::{
+    below_min_extract, log_partial_move, short_extract, DepositResult, Extractor, IntoStorage, Provider, Storage,
+};
 use abort_on_drop::ChildTask;
 use flexstr::LocalStr;
 use std::{
@@ -18,13 +21,25 @@ use std::{
 pub struct ChestConfig {
     pub accesses: Vec<BusAccess>,
     pub override_max_stack_size: Option<Box<dyn Fn(i32) -> i32>>,
+    pub stack_overrides: Vec<(Filter, i32)>,
+    pub min_extract_count: i32,
+    pub priority: i32,
+    pub weight: Option<f64>,
+    // Deposits skip items matching `reject`, then items not matching a non-empty `accept`.
+    pub accept: Vec<Filter>,
+    pub reject: Vec<Filter>,
+    pub allow_deposit: bool,
+    pub allow_extract: bool,
 }
 
 impl ChestConfig {
-    fn max_size(&self, orig_max_size: i32) -> i32 {
+    fn max_size(&self, item: &Item, detail: &Detail) -> i32 {
+        if let Some(&(_, size)) = self.stack_overrides.iter().find(|(filter, _)| filter.apply(item, detail)) {
+            return size;
+        }
         match &self.override_max_stack_size {
-            Some(f) => f(orig_max_size),
-            None => orig_max_size,
+            Some(f) => f(detail.max_size),
+            None => detail.max_size,
         }
     }
 }
@@ -65,7 +80,36 @@ impl IntoStorage for ChestConfig {
     }
 }
 
+impl ChestStorage {
+    fn transfer(&self, func: &'static str, from_slot: usize, size: i32, to_slot: usize) -> ActionFuture<Call> {
+        let server = self.server.borrow();
+        let access = server.load_balance(&self.config.accesses);
+        let action = ActionFuture::from(Call {
+            addr: access.bus_addr.clone(),
+            args: vec![
+                func.into(),
+                access.inv_addr.clone().into(),
+                (from_slot + 1).into(),
+                size.into(),
+                (to_slot + 1).into(),
+            ],
+        });
+        server.enqueue_request_group(&access.client, vec![action.clone().into()]);
+        action
+    }
+}
+
 impl Storage for ChestStorage {
+    fn name(&self) -> &LocalStr { &self.config.accesses[0].inv_addr }
+    fn stacks(&self) -> &[Option<DetailStack>] { &self.stacks }
+    fn insertion_priority(&self) -> i32 { self.config.priority }
+    fn weight(&self) -> Option<f64> { self.config.weight }
+
+    fn accepts(&self, item: &Item, detail: &Detail) -> bool {
+        let matches = |filters: &[Filter]| filters.iter().any(|filter| filter.apply(item, detail));
+        !matches(&self.config.reject) && (self.config.accept.is_empty() || matches(&self.config.accept))
+    }
+
     fn update(&self) -> ChildTask<Result<(), LocalStr>> {
         let stacks = list_inventory(self);
         let weak = self.weak.clone();
@@ -74,13 +118,19 @@ impl Storage for ChestStorage {
             alive_mut!(weak, this);
             this.stacks = stacks;
             upgrade_mut!(this.factory, factory);
+            let locked = below_min_extract(&this.stacks, this.config.min_extract_count);
             for (inv_slot, stack) in this.stacks.iter().enumerate() {
                 if let Some(stack) = stack {
-                    factory.register_stored_item(stack.item.clone(), &stack.detail).provide(Provider {
-                        priority: -stack.size,
-                        n_provided: stack.size.into(),
-                        extractor: Rc::new(ChestExtractor { weak: weak.clone(), inv_slot }),
-                    });
+                    let info = factory.register_stored_item(stack.item.clone(), &stack.detail);
+                    if !this.config.allow_extract || locked.contains(&stack.item) {
+                        info.provide_locked(stack.size)
+                    } else {
+                        info.provide(Provider {
+                            priority: -stack.size,
+                            n_provided: stack.size.into(),
+                            extractor: Rc::new(ChestExtractor { weak: weak.clone(), inv_slot }),
+                        })
+                    }
                 }
             }
             Ok(())
@@ -90,11 +140,14 @@ impl Storage for ChestStorage {
     fn cleanup(&mut self) { self.stacks.clear() }
 
     fn deposit_priority(&mut self, item: &Rc<Item>, detail: &Rc<Detail>) -> Option<i32> {
+        if !self.config.allow_deposit || !self.accepts(item, detail) {
+            return None;
+        }
         let mut empty_slot = None;
         let mut size_of_best_slot = None;
         for (inv_slot, stack) in self.stacks.iter().enumerate() {
             if let Some(stack) = stack {
-                if stack.item == *item && stack.size < self.config.max_size(detail.max_size) {
+                if stack.item == *item && stack.size < self.config.max_size(item, detail) {
                     if let Some(best_size) = size_of_best_slot {
                         if stack.size <= best_size {
                             continue;
@@ -117,29 +170,38 @@ impl Storage for ChestStorage {
 
     fn deposit(&mut self, stack: &DetailStack, bus_slot: usize) -> DepositResult {
         let inv_slot = self.inv_slot_to_deposit;
+        let max_size = self.config.max_size(&stack.item, &stack.detail);
         let inv_stack = &mut self.stacks[inv_slot];
         let n_deposited;
         if let Some(inv_stack) = inv_stack {
-            n_deposited = min(stack.size, self.config.max_size(inv_stack.detail.max_size) - inv_stack.size);
+            n_deposited = min(stack.size, max_size - inv_stack.size);
             inv_stack.size += n_deposited
         } else {
-            n_deposited = stack.size;
-            *inv_stack = Some(stack.clone())
+            n_deposited = min(stack.size, max_size);
+            *inv_stack = Some(DetailStack { size: n_deposited, ..stack.clone() })
         }
-        let server = self.server.borrow();
-        let access = server.load_balance(&self.config.accesses);
-        let action = ActionFuture::from(Call {
-            addr: access.bus_addr.clone(),
-            args: vec![
-                "pushItems".into(),
-                access.inv_addr.clone().into(),
-                (bus_slot + 1).into(),
-                n_deposited.into(),
-                (inv_slot + 1).into(),
-            ],
+        let action = self.transfer("pushItems", bus_slot, n_deposited, inv_slot);
+        let retry = self.server.borrow().retry;
+        let weak = self.weak.clone();
+        let label = stack.detail.label.clone();
+        let task = spawn(async move {
+            let reissue = || Ok(alive(&weak)?.borrow().transfer("pushItems", bus_slot, n_deposited, inv_slot));
+            let TransferResult { n_moved } = retry.run(action, reissue).await?.try_into()?;
+            if n_moved < n_deposited {
+                alive_mut!(weak, this);
+                if let Some(inv_stack) = this.stacks.get_mut(inv_slot) {
+                    if let Some(stack) = inv_stack {
+                        stack.size -= n_deposited - n_moved;
+                        if stack.size <= 0 {
+                            *inv_stack = None
+                        }
+                    }
+                }
+                upgrade!(this.factory, factory);
+                log_partial_move(factory, "pushItems", &label, n_moved, n_deposited)
+            }
+            Ok(())
         });
-        server.enqueue_request_group(&access.client, vec![action.clone().into()]);
-        let task = spawn(async move { action.await.map(|_| ()) });
         DepositResult { n_deposited, task }
     }
 }
@@ -148,30 +210,20 @@ impl Extractor for ChestExtractor {
     fn extract(&self, size: i32, bus_slot: usize) -> ChildTask<Result<(), LocalStr>> {
         let inv_slot = self.inv_slot;
         upgrade!(self.weak, this);
-        let server = this.server.borrow();
-        let access = server.load_balance(&this.config.accesses);
-        let action = ActionFuture::from(Call {
-            addr: access.bus_addr.clone(),
-            args: vec![
-                "pullItems".into(),
-                access.inv_addr.clone().into(),
-                (inv_slot + 1).into(),
-                size.into(),
-                (bus_slot + 1).into(),
-            ],
-        });
-        server.enqueue_request_group(&access.client, vec![action.clone().into()]);
+        let action = this.transfer("pullItems", inv_slot, size, bus_slot);
+        let retry = this.server.borrow().retry;
         let weak = self.weak.clone();
         spawn(async move {
-            action.await?;
+            let reissue = || Ok(alive(&weak)?.borrow().transfer("pullItems", inv_slot, size, bus_slot));
+            let TransferResult { n_moved } = retry.run(action, reissue).await?.try_into()?;
             alive_mut!(weak, this);
             let inv_stack = &mut this.stacks[inv_slot];
             let inv_size = &mut inv_stack.as_mut().unwrap().size;
-            *inv_size -= size;
+            *inv_size -= n_moved;
             if *inv_size <= 0 {
                 *inv_stack = None;
             }
-            Ok(())
+            short_extract(n_moved, size)
         })
     }
 }
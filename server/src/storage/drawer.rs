@@ -4,11 +4,15 @@ use super::super::detail_cache::DetailCache;
 use super::super::factory::Factory;
 use super::super::inventory::{list_inventory, Inventory};
 use super::super::item::{Detail, DetailStack, Filter, Item};
+use super::super::lua_value::TransferResult;
 use super::super::server::Server;
 use super::super::util::{alive, spawn};
-use super::{DepositResult, Extractor, IntoStorage, Provider, Storage};
+use super::{
+    below_min_extract, log_partial_move, short_extract, DepositResult, Extractor, IntoStorage, Provider, Storage,
+};
 use abort_on_drop::ChildTask;
 use flexstr::LocalStr;
+use fnv::FnvHashSet;
 use std::{
     cell::RefCell,
     rc::{Rc, Weak},
@@ -17,6 +21,13 @@ use std::{
 pub struct DrawerConfig {
     pub accesses: Vec<BusAccess>,
     pub filters: Vec<Filter>,
+    pub min_extract_count: i32,
+    pub void: bool,
+    pub controller: bool,
+    pub priority: i32,
+    pub weight: Option<f64>,
+    pub allow_deposit: bool,
+    pub allow_extract: bool,
 }
 
 pub struct DrawerStorage {
@@ -26,6 +37,10 @@ pub struct DrawerStorage {
     factory: Weak<RefCell<Factory>>,
     server: Rc<RefCell<Server>>,
     size: Option<usize>,
+    stacks: Vec<Option<DetailStack>>,
+    full: FnvHashSet<Rc<Item>>,
+    // Items a drawer controller already holds, as locked drawers only accept their own type.
+    stored: FnvHashSet<Rc<Item>>,
 }
 
 struct DrawerExtractor {
@@ -46,35 +61,87 @@ impl IntoStorage for DrawerConfig {
                 factory: factory.get_weak().clone(),
                 server: factory.get_server().clone(),
                 size: None,
+                stacks: Vec::new(),
+                full: FnvHashSet::default(),
+                stored: FnvHashSet::default(),
             })
         })
     }
 }
 
+impl DrawerStorage {
+    fn transfer(&self, func: &'static str, from_slot: usize, size: i32, to_slot: Option<usize>) -> ActionFuture<Call> {
+        let server = self.server.borrow();
+        let access = server.load_balance(&self.config.accesses);
+        let mut args = vec![func.into(), access.inv_addr.clone().into(), (from_slot + 1).into(), size.into()];
+        if let Some(to_slot) = to_slot {
+            args.push((to_slot + 1).into())
+        }
+        let action = ActionFuture::from(Call { addr: access.bus_addr.clone(), args });
+        server.enqueue_request_group(&access.client, vec![action.clone().into()]);
+        action
+    }
+
+    // Called when a push falls short. Void drawers destroy overflow, so only a regular drawer can fill up.
+    fn mark_full(&mut self, item: Rc<Item>) -> bool {
+        if self.config.void {
+            return false;
+        }
+        self.full.insert(item);
+        true
+    }
+}
+
 impl Storage for DrawerStorage {
+    fn name(&self) -> &LocalStr { &self.config.accesses[0].inv_addr }
+    fn stacks(&self) -> &[Option<DetailStack>] { &self.stacks }
+    fn insertion_priority(&self) -> i32 { self.config.priority }
+    fn weight(&self) -> Option<f64> { self.config.weight }
+    fn accepts(&self, _: &Item, _: &Detail) -> bool { true }
+
     fn update(&self) -> ChildTask<Result<(), LocalStr>> {
         let stacks = list_inventory(self);
         let weak = self.weak.clone();
         spawn(async move {
             let stacks = stacks.await?;
-            alive!(weak, this);
+            alive_mut!(weak, this);
             upgrade_mut!(this.factory, factory);
-            for (inv_slot, stack) in stacks.into_iter().enumerate() {
+            let locked = below_min_extract(&stacks, this.config.min_extract_count);
+            for (inv_slot, stack) in stacks.iter().enumerate() {
                 if let Some(stack) = stack {
-                    factory.register_stored_item(stack.item, &stack.detail).provide(Provider {
-                        priority: i32::MIN,
-                        n_provided: stack.size.into(),
-                        extractor: Rc::new(DrawerExtractor { weak: weak.clone(), inv_slot }),
-                    });
+                    if this.config.controller {
+                        this.stored.insert(stack.item.clone());
+                    }
+                    let info = factory.register_stored_item(stack.item.clone(), &stack.detail);
+                    if !this.config.allow_extract || locked.contains(&stack.item) {
+                        info.provide_locked(stack.size)
+                    } else {
+                        info.provide(Provider {
+                            priority: i32::MIN,
+                            n_provided: stack.size.into(),
+                            extractor: Rc::new(DrawerExtractor { weak: weak.clone(), inv_slot }),
+                        })
+                    }
                 }
             }
+            this.stacks = stacks;
             Ok(())
         })
     }
 
-    fn cleanup(&mut self) {}
+    fn cleanup(&mut self) {
+        self.stacks.clear();
+        self.full.clear();
+        self.stored.clear()
+    }
 
     fn deposit_priority(&mut self, item: &Rc<Item>, detail: &Rc<Detail>) -> Option<i32> {
+        if !self.config.allow_deposit || self.full.contains(item) {
+            return None;
+        }
+        if self.stored.contains(item) {
+            return Some(i32::MAX);
+        }
         for filter in &self.config.filters {
             if filter.apply(item, detail) {
                 return Some(i32::MAX);
@@ -85,14 +152,23 @@ impl Storage for DrawerStorage {
 
     fn deposit(&mut self, stack: &DetailStack, bus_slot: usize) -> DepositResult {
         let n_deposited = stack.size;
-        let server = self.server.borrow();
-        let access = server.load_balance(&self.config.accesses);
-        let action = ActionFuture::from(Call {
-            addr: access.bus_addr.clone(),
-            args: vec!["pushItems".into(), access.inv_addr.clone().into(), (bus_slot + 1).into(), n_deposited.into()],
+        let action = self.transfer("pushItems", bus_slot, n_deposited, None);
+        let retry = self.server.borrow().retry;
+        let weak = self.weak.clone();
+        let item = stack.item.clone();
+        let label = stack.detail.label.clone();
+        let task = spawn(async move {
+            let reissue = || Ok(alive(&weak)?.borrow().transfer("pushItems", bus_slot, n_deposited, None));
+            let TransferResult { n_moved } = retry.run(action, reissue).await?.try_into()?;
+            if n_moved < n_deposited {
+                alive_mut!(weak, this);
+                if this.mark_full(item) {
+                    upgrade!(this.factory, factory);
+                    log_partial_move(factory, "pushItems", &label, n_moved, n_deposited)
+                }
+            }
+            Ok(())
         });
-        server.enqueue_request_group(&access.client, vec![action.clone().into()]);
-        let task = spawn(async move { action.await.map(|_| ()) });
         DepositResult { n_deposited, task }
     }
 }
@@ -100,19 +176,52 @@ impl Storage for DrawerStorage {
 impl Extractor for DrawerExtractor {
     fn extract(&self, size: i32, bus_slot: usize) -> ChildTask<Result<(), LocalStr>> {
         upgrade!(self.weak, this);
-        let server = this.server.borrow();
-        let access = server.load_balance(&this.config.accesses);
-        let action = ActionFuture::from(Call {
-            addr: access.bus_addr.clone(),
-            args: vec![
-                "pullItems".into(),
-                access.inv_addr.clone().into(),
-                (self.inv_slot + 1).into(),
-                size.into(),
-                (bus_slot + 1).into(),
-            ],
-        });
-        server.enqueue_request_group(&access.client, vec![action.clone().into()]);
-        spawn(async move { action.await.map(|_| ()) })
+        let inv_slot = self.inv_slot;
+        let action = this.transfer("pullItems", inv_slot, size, Some(bus_slot));
+        let retry = this.server.borrow().retry;
+        let weak = self.weak.clone();
+        spawn(async move {
+            let reissue = || Ok(alive(&weak)?.borrow().transfer("pullItems", inv_slot, size, Some(bus_slot)));
+            let TransferResult { n_moved } = retry.run(action, reissue).await?.try_into()?;
+            short_extract(n_moved, size)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::factory::tests::{stock, with_test_factory};
+
+    fn config(void: bool) -> DrawerConfig {
+        DrawerConfig {
+            accesses: Vec::new(),
+            filters: vec![Filter::Name("minecraft:cobblestone".into())],
+            min_extract_count: 0,
+            void,
+            controller: false,
+            priority: 0,
+            weight: None,
+            allow_deposit: true,
+            allow_extract: true,
+        }
+    }
+
+    #[test]
+    fn void_drawer_never_reports_full() {
+        with_test_factory(|factory, _| async move {
+            let item = stock(&mut factory.borrow_mut(), "minecraft:cobblestone", 0);
+            let detail = factory.borrow().items[&item].borrow().detail.clone();
+            for void in [false, true] {
+                let drawer = config(void).into_storage(&factory.borrow());
+                let mut drawer = drawer.borrow_mut();
+                assert_eq!(drawer.deposit_priority(&item, &detail), Some(i32::MAX));
+                // Each round stands in for a pushItems that moved less than asked.
+                for _ in 0..3 {
+                    assert_eq!(drawer.mark_full(item.clone()), !void)
+                }
+                assert_eq!(drawer.deposit_priority(&item, &detail).is_some(), void);
+            }
+        })
     }
 }
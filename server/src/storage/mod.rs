@@ -1,7 +1,8 @@
 use super::factory::Factory;
 use super::item::{Detail, DetailStack, Item};
 use abort_on_drop::ChildTask;
-use flexstr::LocalStr;
+use flexstr::{local_fmt, LocalStr};
+use fnv::{FnvHashMap, FnvHashSet};
 use std::{
     cell::{Cell, RefCell},
     cmp::Ordering,
@@ -14,6 +15,11 @@ pub struct DepositResult {
 }
 
 pub trait Storage: 'static {
+    fn name(&self) -> &LocalStr;
+    fn stacks(&self) -> &[Option<DetailStack>];
+    fn insertion_priority(&self) -> i32;
+    fn weight(&self) -> Option<f64>;
+    fn accepts(&self, item: &Item, detail: &Detail) -> bool;
     fn update(&self) -> ChildTask<Result<(), LocalStr>>;
     fn cleanup(&mut self);
     fn deposit_priority(&mut self, item: &Rc<Item>, detail: &Rc<Detail>) -> Option<i32>;
@@ -49,7 +55,61 @@ impl Ord for Provider {
     fn cmp(&self, other: &Self) -> Ordering { self.priority.cmp(&other.priority) }
 }
 
+fn log_partial_move(factory: &Factory, func: &str, label: &str, n_moved: i32, n_requested: i32) {
+    factory.config.tui.log(format!("{func} {label}: moved {n_moved} of {n_requested}"), 8)
+}
+
+fn short_extract(n_moved: i32, n_requested: i32) -> Result<(), LocalStr> {
+    if n_moved < n_requested {
+        Err(local_fmt!("pullItems moved {} of {}", n_moved, n_requested))
+    } else {
+        Ok(())
+    }
+}
+
+fn below_min_extract(stacks: &[Option<DetailStack>], min_extract_count: i32) -> FnvHashSet<Rc<Item>> {
+    let mut totals = FnvHashMap::<Rc<Item>, i32>::default();
+    for stack in stacks.iter().flatten() {
+        *totals.entry(stack.item.clone()).or_default() += stack.size
+    }
+    totals.into_iter().filter(|&(_, n)| n < min_extract_count).map(|(item, _)| item).collect()
+}
+
 mod chest;
 mod drawer;
 pub use chest::*;
 pub use drawer::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_value::Table;
+
+    fn stack(name: &str, size: i32) -> Option<DetailStack> {
+        let item = Rc::new(Item {
+            name: name.to_owned().into(),
+            nbt_hash: None,
+            #[cfg(feature = "plethora")]
+            damage: 0,
+        });
+        let detail = Rc::new(Detail { label: name.to_owned().into(), max_size: 64, others: Table::new() });
+        Some(DetailStack { item, detail, size })
+    }
+
+    fn locked_names(stacks: &[Option<DetailStack>], min_extract_count: i32) -> Vec<String> {
+        let mut names: Vec<_> =
+            below_min_extract(stacks, min_extract_count).iter().map(|x| x.name.to_string()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn item_below_minimum_is_held_until_enough_accumulates() {
+        let mut stacks = vec![stack("minecraft:cobblestone", 3), None, stack("minecraft:dirt", 10)];
+        assert_eq!(locked_names(&stacks, 8), ["minecraft:cobblestone"]);
+        // Counts add up over every slot holding the item.
+        stacks[1] = stack("minecraft:cobblestone", 5);
+        assert!(locked_names(&stacks, 8).is_empty());
+        assert!(locked_names(&stacks, 0).is_empty());
+    }
+}
@@ -1,12 +1,18 @@
 use abort_on_drop::ChildTask;
-use flexstr::{local_str, LocalStr};
+use flexstr::{local_fmt, local_str, LocalStr};
 use futures_util::join;
+use serde::Serialize;
 use std::{
     cell::RefCell,
+    collections::hash_map::RandomState,
+    fs::{rename, File},
     future::Future,
+    hash::{BuildHasher, Hasher},
+    io::{BufWriter, Write},
     pin::Pin,
     rc::{Rc, Weak},
     task::{Context, Poll, Waker},
+    time::Duration,
 };
 use tokio::task::spawn_local;
 
@@ -61,6 +67,15 @@ pub async fn join_pair<A, B>(
     }
 }
 
+pub fn jitter(base: Duration, jitter: Duration) -> Duration {
+    let range = jitter.as_millis() as u64;
+    if range == 0 {
+        return base;
+    }
+    let offset = RandomState::new().build_hasher().finish() % (2 * range + 1);
+    (base + Duration::from_millis(offset)).saturating_sub(jitter)
+}
+
 struct LocalOneShotState<T> {
     result: Option<Result<T, LocalStr>>,
     waker: Option<Waker>,
@@ -127,6 +142,16 @@ macro_rules! upgrade_mut {
     };
 }
 
+// Writes to a temporary file first so readers never see a partially written file.
+pub fn write_json_atomic(path: &str, value: &impl Serialize) -> Result<(), LocalStr> {
+    let tmp_path = format!("{path}.tmp");
+    let mut file = BufWriter::new(File::create(&tmp_path).map_err(|e| local_fmt!("{tmp_path}: {e}"))?);
+    serde_json::to_writer_pretty(&mut file, value).map_err(|e| local_fmt!("{tmp_path}: {e}"))?;
+    file.flush().map_err(|e| local_fmt!("{tmp_path}: {e}"))?;
+    drop(file);
+    rename(&tmp_path, path).map_err(|e| local_fmt!("{path}: {e}"))
+}
+
 pub fn alive<T>(weak: &Weak<T>) -> Result<Rc<T>, LocalStr> { weak.upgrade().ok_or_else(|| local_str!("owner died")) }
 
 macro_rules! alive {
@@ -144,3 +169,18 @@ macro_rules! alive_mut {
         let $v = &mut *$v;
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn jitter_varies_within_bounds() {
+        let (base, spread) = (Duration::from_secs(1), Duration::from_millis(200));
+        let intervals: BTreeSet<_> = (0..100).map(|_| jitter(base, spread)).collect();
+        assert!(intervals.iter().all(|x| (base - spread..=base + spread).contains(x)), "{intervals:?}");
+        assert!(intervals.len() > 1);
+        assert_eq!(jitter(base, Duration::ZERO), base);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::{util::spawn, Tui};
+use abort_on_drop::ChildTask;
+use flexstr::{local_fmt, LocalStr};
+use serde_json::json;
+use std::{
+    cell::RefCell,
+    mem::take,
+    rc::{Rc, Weak},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::Notify,
+    time::{sleep, timeout},
+};
+
+// Messages are batched into at most one post per interval so a flapping condition can't spam the channel.
+const MIN_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_QUEUED: usize = 50;
+// Discord rejects messages longer than this.
+const MAX_CONTENT: usize = 2000;
+
+struct Target {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+#[derive(Default)]
+struct Pending {
+    lines: Vec<LocalStr>,
+    n_dropped: usize,
+}
+
+pub struct Webhook {
+    pending: Rc<RefCell<Pending>>,
+    on_push: Rc<Notify>,
+    _task: ChildTask<()>,
+}
+
+// Only plain http URLs are supported; point https services like Discord at a local relay.
+fn parse_url(url: &str) -> Result<Target, LocalStr> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| local_fmt!("{url}: only http:// URLs are supported"))?;
+    let (authority, path) = rest.find('/').map_or((rest, "/"), |i| (&rest[..i], &rest[i..]));
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| local_fmt!("{url}: invalid port"))?),
+        None => (authority, 80),
+    };
+    Ok(Target { host: host.to_owned(), port, path: path.to_owned() })
+}
+
+impl Webhook {
+    pub fn new(url: &str, tui: Rc<Tui>) -> Result<Self, LocalStr> {
+        let target = parse_url(url)?;
+        let pending = Rc::new(RefCell::new(Pending::default()));
+        let on_push = Rc::new(Notify::new());
+        let task = spawn(webhook_main(target, tui, Rc::downgrade(&pending), on_push.clone()));
+        Ok(Self { pending, on_push, _task: task })
+    }
+
+    pub fn push(&self, text: &LocalStr) {
+        let mut pending = self.pending.borrow_mut();
+        if pending.lines.len() < MAX_QUEUED {
+            pending.lines.push(text.clone());
+            self.on_push.notify_one()
+        } else {
+            pending.n_dropped += 1
+        }
+    }
+}
+
+async fn webhook_main(target: Target, tui: Rc<Tui>, pending: Weak<RefCell<Pending>>, on_push: Rc<Notify>) {
+    loop {
+        on_push.notified().await;
+        let Some(pending) = pending.upgrade() else { break };
+        let Pending { lines, n_dropped } = take(&mut *pending.borrow_mut());
+        drop(pending);
+        let mut content = String::new();
+        let mut i = 0;
+        while i < lines.len() {
+            let n_repeats = lines[i..].iter().take_while(|x| **x == lines[i]).count();
+            let line = if n_repeats > 1 { format!("{} (x{n_repeats})\n", lines[i]) } else { format!("{}\n", lines[i]) };
+            i += n_repeats;
+            if content.len() + line.len() > MAX_CONTENT {
+                break;
+            }
+            content += &line
+        }
+        if i < lines.len() || n_dropped > 0 {
+            content += &format!("... and {} more", lines.len() - i + n_dropped)
+        }
+        if !content.is_empty() {
+            let body = json!({ "content": content }).to_string();
+            // A failing webhook only leaves a debug line; the factory keeps running either way.
+            match timeout(MIN_INTERVAL, post(&target, &body)).await {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => tui.log(format!("webhook: {e}"), 8),
+                Err(_) => tui.log("webhook: timed out".to_owned(), 8),
+            }
+        }
+        sleep(MIN_INTERVAL).await
+    }
+}
+
+async fn post(target: &Target, body: &str) -> Result<(), LocalStr> {
+    let mut stream = TcpStream::connect((&*target.host, target.port)).await.map_err(|e| local_fmt!("{e}"))?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        target.path,
+        target.host,
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).await.map_err(|e| local_fmt!("{e}"))?;
+    let mut buf = [0; 64];
+    let n = stream.read(&mut buf).await.map_err(|e| local_fmt!("{e}"))?;
+    let status = String::from_utf8_lossy(&buf[..n]);
+    let status = status.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1).is_some_and(|code| code.starts_with('2')) {
+        Ok(())
+    } else {
+        Err(local_fmt!("{status}"))
+    }
+}